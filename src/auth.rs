@@ -0,0 +1,176 @@
+// Copyright (C) 2026 The cargo-http-registry Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fs::read_to_string;
+use std::path::Path;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::ensure;
+use anyhow::Context as _;
+use anyhow::Result;
+
+use sha2::Digest as _;
+use sha2::Sha256;
+
+
+/// A single token known to the registry.
+#[derive(Debug)]
+struct Token {
+  /// The name of the user the token belongs to.
+  user: String,
+  /// The hex encoded SHA-256 hash of the token.
+  hash: String,
+}
+
+impl Token {
+  /// Parse a token from a line of a token file.
+  ///
+  /// Lines have the form `<user> <sha256-of-token>`.
+  fn parse(line: &str) -> Result<Self> {
+    let mut parts = line.split_whitespace();
+    let (user, hash) = match (parts.next(), parts.next(), parts.next()) {
+      (Some(user), Some(hash), None) => (user, hash),
+      _ => bail!("line `{}` is not of the form `<user> <sha256>`", line),
+    };
+
+    ensure!(
+      hash.len() == 64 && hash.bytes().all(|c| c.is_ascii_hexdigit()),
+      "token hash of user {} is not a hex encoded SHA-256 hash",
+      user
+    );
+
+    let token = Self {
+      user: user.to_string(),
+      hash: hash.to_ascii_lowercase(),
+    };
+    Ok(token)
+  }
+}
+
+
+/// A type checking the tokens provided by clients.
+#[derive(Debug, Default)]
+pub struct Auth {
+  /// The tokens we accept, if authentication is enabled.
+  tokens: Option<Vec<Token>>,
+}
+
+impl Auth {
+  /// Load the tokens from the file at `path`.
+  ///
+  /// The file contains one token per line, as `<user> <sha256>`, where
+  /// `<sha256>` is the hex encoded SHA-256 hash of the token. Empty
+  /// lines and lines starting with `#` are ignored.
+  pub fn load(path: &Path) -> Result<Self> {
+    let content = read_to_string(path)
+      .with_context(|| format!("failed to read token file {}", path.display()))?;
+
+    let tokens = content
+      .lines()
+      .map(str::trim)
+      .filter(|line| !line.is_empty() && !line.starts_with('#'))
+      .map(Token::parse)
+      .collect::<Result<Vec<_>>>()
+      .with_context(|| format!("failed to parse token file {}", path.display()))?;
+
+    let auth = Self {
+      tokens: Some(tokens),
+    };
+    Ok(auth)
+  }
+
+  /// Check whether a user with the given name is known.
+  ///
+  /// Without authentication enabled no users are known.
+  pub fn has_user(&self, user: &str) -> bool {
+    self
+      .tokens
+      .iter()
+      .flatten()
+      .any(|token| token.user == user)
+  }
+
+  /// Authenticate a request carrying `token` in its `Authorization`
+  /// header.
+  ///
+  /// On success, the name of the user owning the token is returned, or
+  /// `None` if authentication is disabled.
+  pub fn authenticate(&self, token: Option<&str>) -> Result<Option<&str>> {
+    let tokens = match &self.tokens {
+      Some(tokens) => tokens,
+      None => return Ok(None),
+    };
+
+    let token = token.ok_or_else(|| anyhow!("authentication required but no token provided"))?;
+    let hash = format!("{:x}", Sha256::digest(token.as_bytes()));
+
+    tokens
+      .iter()
+      .find(|token| token.hash == hash)
+      .map(|token| Some(token.user.as_str()))
+      .ok_or_else(|| anyhow!("the provided token is invalid"))
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::fs::write;
+
+  use tempfile::tempdir;
+
+
+  /// Check that authentication is a no-op when no tokens are configured.
+  #[test]
+  fn disabled_authentication() {
+    let auth = Auth::default();
+    assert_eq!(auth.authenticate(None).unwrap(), None);
+    assert_eq!(auth.authenticate(Some("foobar")).unwrap(), None);
+    assert!(!auth.has_user("foobar"));
+  }
+
+  /// Check that we can authenticate tokens loaded from a file.
+  #[test]
+  fn token_authentication() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("tokens");
+    let data = format!(
+      "# A comment.\n\nalice {:x}\nbob {:X}\n",
+      Sha256::digest(b"secret1"),
+      Sha256::digest(b"secret2"),
+    );
+    let () = write(&path, data).unwrap();
+
+    let auth = Auth::load(&path).unwrap();
+    assert_eq!(auth.authenticate(Some("secret1")).unwrap(), Some("alice"));
+    assert_eq!(auth.authenticate(Some("secret2")).unwrap(), Some("bob"));
+
+    assert!(auth.has_user("alice"));
+    assert!(!auth.has_user("eve"));
+
+    let err = auth.authenticate(Some("secret3")).unwrap_err();
+    assert_eq!(err.to_string(), "the provided token is invalid");
+    let err = auth.authenticate(None).unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "authentication required but no token provided"
+    );
+  }
+
+  /// Check that malformed token files are reported as such.
+  #[test]
+  fn invalid_token_file() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("tokens");
+    let () = write(&path, "alice deadbeef\n").unwrap();
+
+    let err = Auth::load(&path).unwrap_err();
+    assert_eq!(
+      err.root_cause().to_string(),
+      "token hash of user alice is not a hex encoded SHA-256 hash"
+    );
+  }
+}
@@ -0,0 +1,224 @@
+// Copyright (C) 2026 The cargo-http-registry Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fs::read_to_string;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::Context as _;
+use anyhow::Result;
+
+use serde::Deserialize;
+
+use cargo_http_registry::ManifestField;
+
+
+/// The name of the configuration file looked up in the registry root if
+/// none was provided explicitly.
+const CONFIG_FILE_NAME: &str = "registry.toml";
+
+
+/// The `[git]` section of the configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct GitConfig {
+  /// The name to author commits to the index as.
+  pub name: Option<String>,
+  /// The email address to author commits to the index as.
+  pub email: Option<String>,
+}
+
+/// The `[tls]` section of the configuration file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct TlsConfig {
+  /// A PEM file containing the certificate chain.
+  pub cert: PathBuf,
+  /// A PEM file containing the private key.
+  pub key: PathBuf,
+}
+
+/// The `[publish]` section of the configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct PublishConfig {
+  /// The maximum size of a `.crate` file, in bytes.
+  pub max_crate_size: Option<u64>,
+  /// The maximum size of the unpacked contents of a `.crate` file, in
+  /// bytes.
+  pub max_unpacked_size: Option<u64>,
+  /// Manifest fields that published crates have to specify.
+  pub required_fields: Vec<ManifestField>,
+  /// The SPDX license identifiers crates may be published under.
+  pub allowed_licenses: Vec<String>,
+  /// Whether to check that dependencies of published crates resolve.
+  pub check_dependencies: bool,
+  /// The index URLs of registries that dependencies may come from.
+  pub allowed_registries: Vec<String>,
+}
+
+/// The contents of a configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+  /// The root directory of the registry.
+  pub root: Option<PathBuf>,
+  /// The single address to listen on. Serving on multiple addresses
+  /// is not supported.
+  pub listen_addr: Option<SocketAddr>,
+  /// The URL under which the registry is reachable by clients.
+  pub public_url: Option<String>,
+  /// A file containing hashed tokens.
+  pub token_file: Option<PathBuf>,
+  /// Whether to require authentication for read accesses.
+  pub auth_required: bool,
+  /// Whether to serve an existing registry read-only.
+  pub read_only: bool,
+  /// The certificate and key to serve HTTPS with.
+  pub tls: Option<TlsConfig>,
+  /// The number of threads serving requests.
+  pub workers: Option<NonZeroUsize>,
+  /// Settings concerning the git repository backing the index.
+  pub git: GitConfig,
+  /// The publish policy.
+  pub publish: PublishConfig,
+}
+
+impl Config {
+  /// Load the configuration file at `path`.
+  ///
+  /// Relative paths in the file are interpreted relative to the
+  /// directory containing it.
+  pub fn load(path: &Path) -> Result<Self> {
+    let content = read_to_string(path)
+      .with_context(|| format!("failed to read configuration file {}", path.display()))?;
+    let mut config = toml::from_str::<Self>(&content)
+      .with_context(|| format!("failed to parse configuration file {}", path.display()))?;
+
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    config.root = config.root.map(|root| dir.join(root));
+    config.token_file = config.token_file.map(|file| dir.join(file));
+    if let Some(tls) = &mut config.tls {
+      tls.cert = dir.join(&tls.cert);
+      tls.key = dir.join(&tls.key);
+    }
+    Ok(config)
+  }
+
+  /// Find and load the configuration to use.
+  ///
+  /// An explicitly provided `path` has to exist. Otherwise, we use
+  /// `registry.toml` inside the registry `root`, if present, and fall
+  /// back to the default configuration.
+  pub fn find(path: Option<&Path>, root: Option<&Path>) -> Result<Self> {
+    if let Some(path) = path {
+      return Self::load(path)
+    }
+
+    match root.map(|root| root.join(CONFIG_FILE_NAME)) {
+      Some(path) if path.is_file() => Self::load(&path),
+      _ => Ok(Self::default()),
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::fs::write;
+
+  use tempfile::tempdir;
+
+
+  /// Check that we can load a complete configuration file.
+  #[test]
+  fn config_loading() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("config.toml");
+    let content = r#"
+root = "registry"
+listen-addr = "0.0.0.0:8080"
+public-url = "https://example.com/registry"
+token-file = "/etc/tokens"
+auth-required = true
+read-only = true
+workers = 4
+
+[tls]
+cert = "cert.pem"
+key = "/etc/key.pem"
+
+[git]
+name = "Registry"
+email = "registry@example.org"
+
+[publish]
+max-crate-size = 1024
+required-fields = ["license", "description"]
+allowed-licenses = ["MIT"]
+check-dependencies = true
+"#;
+    let () = write(&path, content).unwrap();
+
+    let config = Config::load(&path).unwrap();
+    assert_eq!(config.root, Some(dir.path().join("registry")));
+    assert_eq!(config.listen_addr, Some("0.0.0.0:8080".parse().unwrap()));
+    assert_eq!(
+      config.public_url.as_deref(),
+      Some("https://example.com/registry")
+    );
+    assert_eq!(config.token_file, Some(PathBuf::from("/etc/tokens")));
+    assert!(config.auth_required);
+    assert!(config.read_only);
+    assert_eq!(config.workers, NonZeroUsize::new(4));
+    let tls = config.tls.unwrap();
+    assert_eq!(tls.cert, dir.path().join("cert.pem"));
+    assert_eq!(tls.key, PathBuf::from("/etc/key.pem"));
+    assert_eq!(config.git.name.as_deref(), Some("Registry"));
+    assert_eq!(config.publish.max_crate_size, Some(1024));
+    assert_eq!(config.publish.max_unpacked_size, None);
+    assert_eq!(
+      config.publish.required_fields,
+      vec![ManifestField::License, ManifestField::Description]
+    );
+    assert!(config.publish.check_dependencies);
+    assert!(config.publish.allowed_registries.is_empty());
+  }
+
+  /// Check that unknown keys are rejected.
+  #[test]
+  fn unknown_keys() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("config.toml");
+
+    let () = write(&path, "adr = \"0.0.0.0:8080\"\n").unwrap();
+    let err = Config::load(&path).unwrap_err();
+    assert!(format!("{err:#}").contains("unknown field `adr`"), "{err:#}");
+
+    let () = write(&path, "[publish]\nmax-size = 1\n").unwrap();
+    let err = Config::load(&path).unwrap_err();
+    assert!(format!("{err:#}").contains("unknown field `max-size`"), "{err:#}");
+
+    let () = write(&path, "workers = 0\n").unwrap();
+    assert!(Config::load(&path).is_err());
+  }
+
+  /// Check that we look up the configuration file in the right places.
+  #[test]
+  fn config_lookup() {
+    let root = tempdir().unwrap();
+    let config = Config::find(None, Some(root.path())).unwrap();
+    assert_eq!(config.listen_addr, None);
+
+    let () = write(root.path().join(CONFIG_FILE_NAME), "listen-addr = \"127.0.0.1:1\"\n").unwrap();
+    let config = Config::find(None, Some(root.path())).unwrap();
+    assert_eq!(config.listen_addr, Some("127.0.0.1:1".parse().unwrap()));
+
+    let path = root.path().join("does-not-exist.toml");
+    assert!(Config::find(Some(&path), Some(root.path())).is_err());
+  }
+}
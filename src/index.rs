@@ -5,6 +5,12 @@ use std::collections::BTreeMap;
 use std::env;
 use std::ffi::OsString;
 use std::fs::create_dir_all;
+use std::fs::read;
+use std::fs::read_dir;
+use std::fs::read_to_string;
+use std::fs::remove_file;
+use std::fs::rename;
+use std::fs::write;
 use std::fs::File;
 use std::fs::OpenOptions;
 use std::io;
@@ -12,27 +18,110 @@ use std::io::ErrorKind;
 use std::net::SocketAddr;
 use std::path::Path;
 use std::path::PathBuf;
-use std::process::Command;
 use std::str::FromStr as _;
 
 use anyhow::anyhow;
-use anyhow::ensure;
 use anyhow::Context as _;
 use anyhow::Result;
 
+use git2::ObjectType;
+use git2::Oid;
 use git2::Repository;
 use git2::Signature;
 
 use serde::Deserialize;
 use serde::Serialize;
 use serde_json::from_reader;
+use serde_json::from_str;
 use serde_json::to_writer_pretty;
 
+use tracing::error;
+
+use crate::lock::RootLock;
+
 
 /// The default user to use when creating a commit.
 const GIT_USER: &str = "cargo-http-registry";
 /// The default email to use when creating a commit.
 const GIT_EMAIL: &str = "cargo-http-registry@example.com";
+/// The pattern of per-version crate meta data files, which are kept
+/// out of the git repository.
+const CRATE_META_PATTERN: &str = "/*-*.json";
+
+
+/// The identity to author git commits as.
+///
+/// Unset values fall back to the `GIT_AUTHOR_NAME` and
+/// `GIT_AUTHOR_EMAIL` environment variables and, if those are not set
+/// either, to built-in defaults.
+#[derive(Clone, Debug, Default)]
+pub struct GitAuthor {
+  /// The name of the author.
+  pub name: Option<String>,
+  /// The email address of the author.
+  pub email: Option<String>,
+}
+
+
+/// Options controlling how an [`Index`] is set up.
+#[derive(Clone, Debug, Default)]
+pub struct IndexOptions {
+  /// Whether clients are expected to authenticate for all requests,
+  /// not just mutating ones.
+  pub auth_required: bool,
+  /// The identity to author git commits as.
+  pub git_author: GitAuthor,
+  /// Whether to open an existing index as is, without modifying it.
+  pub read_only: bool,
+}
+
+
+/// A reference in the index' git repository.
+#[derive(Debug)]
+pub struct Ref {
+  /// The full name of the reference.
+  pub name: String,
+  /// The object the reference points to.
+  pub oid: Oid,
+  /// For annotated tags, the object the tag ultimately points to.
+  pub peeled: Option<Oid>,
+}
+
+
+/// Retrieve all references of the given git repository, sorted by name.
+pub fn refs(repository: &Repository) -> Result<Vec<Ref>> {
+  let mut refs = Vec::new();
+  let references = repository
+    .references()
+    .context("failed to list git references")?;
+  for reference in references {
+    let reference = reference.context("failed to read git reference")?;
+    let (name, oid) = match (reference.name(), reference.resolve()) {
+      (Some(name), Ok(resolved)) => match resolved.target() {
+        Some(oid) => (name.to_string(), oid),
+        None => continue,
+      },
+      // Skip references with non-UTF-8 names or dangling symbolic
+      // ones.
+      _ => continue,
+    };
+
+    let object = repository
+      .find_object(oid, None)
+      .with_context(|| format!("failed to find object {} referenced by {}", oid, name))?;
+    let peeled = if object.kind() == Some(ObjectType::Tag) {
+      let peeled = object
+        .peel(ObjectType::Any)
+        .with_context(|| format!("failed to peel tag {}", name))?;
+      Some(peeled.id())
+    } else {
+      None
+    };
+    refs.push(Ref { name, oid, peeled });
+  }
+  refs.sort_by(|ref1, ref2| ref1.name.cmp(&ref2.name));
+  Ok(refs)
+}
 
 
 /// Parse the port from the given URL.
@@ -47,6 +136,50 @@ fn parse_port(url: &str) -> Result<u16> {
 }
 
 
+/// Check whether the provided `bool` is `false`.
+#[inline]
+fn is_false(value: &bool) -> bool {
+  !*value
+}
+
+
+/// Atomically replace the contents of the file at `path` with `data`.
+///
+/// Missing parent directories are created.
+fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+  let dir = path
+    .parent()
+    .ok_or_else(|| anyhow!("path {} has no parent", path.display()))?;
+  let name = path
+    .file_name()
+    .ok_or_else(|| anyhow!("path {} has no file name", path.display()))?;
+
+  create_dir_all(dir).with_context(|| format!("failed to create directory {}", dir.display()))?;
+
+  let mut tmp_name = OsString::from(".");
+  tmp_name.push(name);
+  tmp_name.push(".tmp");
+  let tmp_path = dir.join(tmp_name);
+
+  let result = write(&tmp_path, data)
+    .with_context(|| format!("failed to write {}", tmp_path.display()))
+    .and_then(|()| {
+      rename(&tmp_path, path).with_context(|| {
+        format!(
+          "failed to rename {} to {}",
+          tmp_path.display(),
+          path.display()
+        )
+      })
+    });
+
+  if result.is_err() {
+    let _ = remove_file(&tmp_path);
+  }
+  result
+}
+
+
 /// Create a symbolic link for a directory.
 fn symlink_dir<P, Q>(original: P, link: Q) -> io::Result<()>
 where
@@ -62,7 +195,7 @@ where
 }
 
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Dep {
   /// Name of the dependency. If the dependency is renamed from the
   /// original package name, this is the new name. The original package
@@ -96,7 +229,7 @@ pub struct Dep {
   pub package: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Entry {
   /// The name of the package.
   /// This must only contain alphanumeric, '-', or '_' characters.
@@ -117,6 +250,41 @@ pub struct Entry {
   /// The `links` string value from the package's manifest, or null if
   /// not specified. This field is optional and defaults to null.
   pub links: Option<String>,
+  /// Features using the `dep:` or `?` syntax, which older versions of
+  /// cargo do not understand. If present, `v` should be 2.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub features2: Option<BTreeMap<String, Vec<String>>>,
+  /// The version of the index entry format. A missing value implies
+  /// version 1.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub v: Option<u32>,
+  /// The minimum supported Rust version of the package, if specified.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub rust_version: Option<String>,
+}
+
+
+/// Read all entries from the crate index file at `path`.
+///
+/// A file that does not exist is treated as containing no entries.
+pub fn read_entries(path: &Path) -> Result<Vec<Entry>> {
+  let content = match read_to_string(path) {
+    Ok(content) => content,
+    Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+    Err(err) => {
+      return Err(err)
+        .with_context(|| format!("failed to read crate index file {}", path.display()))
+    },
+  };
+
+  content
+    .lines()
+    .filter(|line| !line.is_empty())
+    .map(|line| {
+      from_str::<Entry>(line)
+        .with_context(|| format!("failed to parse crate index entry in {}", path.display()))
+    })
+    .collect()
 }
 
 
@@ -125,6 +293,10 @@ pub struct Entry {
 struct Config {
   dl: String,
   api: Option<String>,
+  /// Whether clients have to authenticate for all operations, including
+  /// reads (see RFC 3139).
+  #[serde(rename = "auth-required", default, skip_serializing_if = "is_false")]
+  auth_required: bool,
 }
 
 /// A struct representing a crate index.
@@ -137,47 +309,92 @@ pub struct Index {
   git_email: String,
   /// The git repository inside the index.
   repository: Repository,
+  /// The lock preventing other processes from modifying the index, if
+  /// we hold it.
+  _lock: Option<RootLock>,
 }
 
 impl Index {
-  pub fn new<P>(root: P, addr: &SocketAddr) -> Result<Self>
+  /// Create a new `Index` or open an existing one at `root`.
+  ///
+  /// The index advertises itself as being reachable at `public_url`
+  /// or, if not set, at `addr`.
+  ///
+  /// Unless `options.read_only` is set, an exclusive lock on `root` is
+  /// acquired and held for the lifetime of the object, making sure
+  /// that no other process serves the same registry. A read-only index,
+  /// on the other hand, has to exist already and is opened as is,
+  /// without any modifications being made to it.
+  pub fn new<P>(
+    root: P,
+    addr: &SocketAddr,
+    public_url: Option<&str>,
+    options: &IndexOptions,
+  ) -> Result<Self>
   where
     P: Into<PathBuf>,
   {
-    fn inner(root: PathBuf, addr: &SocketAddr) -> Result<Index> {
-      let git_user = env::var_os("GIT_AUTHOR_NAME").unwrap_or_else(|| OsString::from(GIT_USER));
-      let git_user = git_user
-        .to_str()
-        .context("GIT_AUTHOR_NAME does not contain valid UTF-8")?
-        .to_string();
-      let git_email = env::var_os("GIT_AUTHOR_EMAIL").unwrap_or_else(|| OsString::from(GIT_EMAIL));
-      let git_email = git_email
-        .to_str()
-        .context("GIT_AUTHOR_EMAIL does not contain valid UTF-8")?
-        .to_string();
+    fn inner(
+      root: PathBuf,
+      addr: &SocketAddr,
+      public_url: Option<&str>,
+      options: &IndexOptions,
+    ) -> Result<Index> {
+      let git_user = match &options.git_author.name {
+        Some(name) => name.clone(),
+        None => env::var_os("GIT_AUTHOR_NAME")
+          .unwrap_or_else(|| OsString::from(GIT_USER))
+          .to_str()
+          .context("GIT_AUTHOR_NAME does not contain valid UTF-8")?
+          .to_string(),
+      };
+      let git_email = match &options.git_author.email {
+        Some(email) => email.clone(),
+        None => env::var_os("GIT_AUTHOR_EMAIL")
+          .unwrap_or_else(|| OsString::from(GIT_EMAIL))
+          .to_str()
+          .context("GIT_AUTHOR_EMAIL does not contain valid UTF-8")?
+          .to_string(),
+      };
+
+      if options.read_only {
+        let repository = Repository::open(&root)
+          .with_context(|| format!("failed to open git repository {}", root.display()))?;
+        let index = Index {
+          root,
+          git_user,
+          git_email,
+          repository,
+          _lock: None,
+        };
+        return Ok(index)
+      }
 
       create_dir_all(&root)
         .with_context(|| format!("failed to create directory {}", root.display()))?;
 
       let repository = Repository::init(&root)
         .with_context(|| format!("failed to initialize git repository {}", root.display()))?;
+      let lock = RootLock::acquire(&root, addr)?;
 
       let mut index = Index {
         root,
         git_user,
         git_email,
         repository,
+        _lock: Some(lock),
       };
       index.ensure_has_commit()?;
-      index.ensure_config(addr)?;
+      index.ensure_config(addr, public_url, options.auth_required)?;
       index.ensure_index_symlink()?;
+      index.ensure_excludes()?;
       index.update_server_info()?;
 
       Ok(index)
     }
 
     let root = root.into();
-    inner(root, addr)
+    inner(root, addr, public_url, options)
   }
 
   /// Add a file to the index. The path must be relative to the index root.
@@ -197,8 +414,113 @@ impl Index {
     Ok(())
   }
 
+  /// Atomically write the given files and commit them.
+  ///
+  /// Each file, identified by its path relative to the index root, is
+  /// first written to a temporary file and then renamed into place.
+  /// `untracked` files are written the same way after all others, but
+  /// not committed, so that they do not burden clients fetching the
+  /// index via git. If anything fails before the commit has been
+  /// created, the previous contents of all files as well as the state
+  /// of the git index are restored. Once the commit exists the update
+  /// is considered successful and failures to update the auxiliary
+  /// information for git's "dumb" HTTP protocol are merely logged.
+  pub fn update(
+    &mut self,
+    files: &[(&Path, &[u8])],
+    untracked: &[(&Path, &[u8])],
+    message: &str,
+  ) -> Result<()> {
+    let mut previous = Vec::with_capacity(files.len() + untracked.len());
+
+    let result = files
+      .iter()
+      .map(|file| (file, true))
+      .chain(untracked.iter().map(|file| (file, false)))
+      .try_for_each(|((relative_path, data), tracked)| {
+        let path = self.root.join(relative_path);
+        let content = match read(&path) {
+          Ok(content) => Some(content),
+          Err(err) if err.kind() == ErrorKind::NotFound => None,
+          Err(err) => {
+            return Err(err).with_context(|| format!("failed to read {}", path.display()))
+          },
+        };
+        let () = write_atomic(&path, data)?;
+        previous.push((path, content));
+
+        if !tracked {
+          return Ok(())
+        }
+
+        self.add(relative_path).with_context(|| {
+          format!(
+            "failed to add {} to git repository (full path: {})",
+            relative_path.display(),
+            self.root.join(relative_path).display(),
+          )
+        })
+      })
+      .and_then(|()| self.create_commit(message));
+
+    if let Err(err) = result {
+      // Restore files in reverse order, in case the same path was
+      // provided multiple times.
+      for (path, content) in previous.into_iter().rev() {
+        let result = match content {
+          Some(content) => write_atomic(&path, &content),
+          None => remove_file(&path)
+            .with_context(|| format!("failed to remove {}", path.display())),
+        };
+        if let Err(err) = result {
+          error!("failed to roll back {}: {:#}", path.display(), err);
+        }
+      }
+
+      if let Err(err) = self.reset_index() {
+        error!("failed to roll back git index: {:#}", err);
+      }
+      return Err(err)
+    }
+
+    // The changes are committed and visible to clients using the
+    // "smart" protocol, so reporting an error now would be misleading.
+    if let Err(err) = self.update_server_info() {
+      error!("failed to update git server info: {:#}", err);
+    }
+    Ok(())
+  }
+
+  /// Reset the git index to the state of `HEAD`.
+  fn reset_index(&mut self) -> Result<()> {
+    let tree = self
+      .repository
+      .head()
+      .context("failed to retrieve git repository HEAD")?
+      .peel_to_tree()
+      .context("failed to find HEAD tree object in git repository")?;
+    let mut index = self
+      .repository
+      .index()
+      .context("failed to retrieve git repository index")?;
+    index
+      .read_tree(&tree)
+      .context("failed to read HEAD tree into git index")?;
+    index
+      .write()
+      .context("failed to write git repository index")?;
+    Ok(())
+  }
+
   /// Create a commit.
   pub fn commit(&mut self, message: &str) -> Result<()> {
+    let () = self.create_commit(message)?;
+    self.update_server_info()
+  }
+
+  /// Create a commit of the current state of the git index, without
+  /// updating information for serving the repository.
+  fn create_commit(&mut self, message: &str) -> Result<()> {
     let mut index = self
       .repository
       .index()
@@ -243,24 +565,60 @@ impl Index {
       )
     }
     .context("failed to create git commit")?;
-
-    self.update_server_info()?;
     Ok(())
   }
 
   /// Update information necessary for serving the repository in "dumb"
   /// mode.
+  ///
+  /// This is the equivalent of `git update-server-info`: we write
+  /// `info/refs`, listing all references (and what annotated tags point
+  /// to), as well as `objects/info/packs`, listing all pack files.
   fn update_server_info(&self) -> Result<()> {
-    // Neither the git2 crate nor libgit2 itself seem to provide similar
-    // functionality, so we have to fall back to just running the
-    // command.
-    let status = Command::new("git")
-      .current_dir(&self.root)
-      .arg("update-server-info")
-      .status()
-      .context("failed to run git update-server-info")?;
-
-    ensure!(status.success(), "git update-server-info failed");
+    let git_dir = self.repository.path();
+
+    let info_refs = refs(&self.repository)?
+      .into_iter()
+      .map(|reference| {
+        let mut line = format!("{}\t{}\n", reference.oid, reference.name);
+        if let Some(peeled) = reference.peeled {
+          line += &format!("{}\t{}^{{}}\n", peeled, reference.name);
+        }
+        line
+      })
+      .collect::<String>();
+    let () = write_atomic(&git_dir.join("info").join("refs"), info_refs.as_bytes())?;
+
+    let pack_dir = git_dir.join("objects").join("pack");
+    let mut packs = Vec::new();
+    match read_dir(&pack_dir) {
+      Ok(entries) => {
+        for entry in entries {
+          let entry = entry
+            .with_context(|| format!("failed to read directory entry in {}", pack_dir.display()))?;
+          if let Some(name) = entry.file_name().to_str() {
+            if name.starts_with("pack-") && name.ends_with(".pack") {
+              packs.push(name.to_string());
+            }
+          }
+        }
+      },
+      Err(err) if err.kind() == ErrorKind::NotFound => (),
+      Err(err) => {
+        return Err(err).with_context(|| format!("failed to read directory {}", pack_dir.display()))
+      },
+    }
+    packs.sort();
+
+    let mut info_packs = packs
+      .iter()
+      .map(|pack| format!("P {}\n", pack))
+      .collect::<String>();
+    info_packs.push('\n');
+    let () = write_atomic(
+      &git_dir.join("objects").join("info").join("packs"),
+      info_packs.as_bytes(),
+    )?;
     Ok(())
   }
 
@@ -293,20 +651,30 @@ impl Index {
   }
 
   /// Ensure that a valid `config.json` exists and that it is up-to-date.
-  fn ensure_config(&mut self, addr: &SocketAddr) -> Result<()> {
+  fn ensure_config(
+    &mut self,
+    addr: &SocketAddr,
+    public_url: Option<&str>,
+    auth_required: bool,
+  ) -> Result<()> {
+    let api = match public_url {
+      Some(url) => url.trim_end_matches('/').to_string(),
+      None => format!("http://{}", addr),
+    };
+    let dl = format!("{}/api/v1/crates/{{crate}}/{{version}}/download", api);
+
     let path = self.root.join("config.json");
     let result = OpenOptions::new().read(true).write(true).open(&path);
     match result {
       Ok(file) => {
         let mut config = from_reader::<_, Config>(&file).context("failed to parse config.json")?;
-        let dl = format!(
-          "http://{}/api/v1/crates/{{crate}}/{{version}}/download",
-          addr
-        );
-        let api = format!("http://{}", addr);
-        if config.dl != dl || config.api.as_ref() != Some(&api) {
+        if config.dl != dl
+          || config.api.as_ref() != Some(&api)
+          || config.auth_required != auth_required
+        {
           config.dl = dl;
           config.api = Some(api);
+          config.auth_required = auth_required;
 
           let file = OpenOptions::new()
             .write(true)
@@ -326,11 +694,9 @@ impl Index {
       Err(err) if err.kind() == ErrorKind::NotFound => {
         let file = File::create(&path).context("failed to create config.json")?;
         let config = Config {
-          dl: format!(
-            "http://{}/api/v1/crates/{{crate}}/{{version}}/download",
-            addr
-          ),
-          api: Some(format!("http://{}", addr)),
+          dl,
+          api: Some(api),
+          auth_required,
         };
         to_writer_pretty(&file, &config).context("failed to write config.json")?;
 
@@ -374,6 +740,34 @@ impl Index {
     Ok(())
   }
 
+  /// Ensure that git ignores the crate meta data files we store in
+  /// the index' working tree but never commit.
+  fn ensure_excludes(&self) -> Result<()> {
+    let info = self.repository.path().join("info");
+    let () = create_dir_all(&info)
+      .with_context(|| format!("failed to create directory {}", info.display()))?;
+
+    let path = info.join("exclude");
+    let mut content = match read_to_string(&path) {
+      Ok(content) => content,
+      Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+      Err(err) => {
+        return Err(err).with_context(|| format!("failed to read {}", path.display()))
+      },
+    };
+
+    if content.lines().any(|line| line == CRATE_META_PATTERN) {
+      return Ok(())
+    }
+
+    if !content.is_empty() && !content.ends_with('\n') {
+      content.push('\n');
+    }
+    content += CRATE_META_PATTERN;
+    content.push('\n');
+    write_atomic(&path, content.as_bytes())
+  }
+
   /// Retrieve the path to the index' root directory.
   #[inline]
   pub fn root(&self) -> &Path {
@@ -411,7 +805,7 @@ mod tests {
   fn empty_index_repository() {
     let root = tempdir().unwrap();
     let addr = SocketAddr::from_str("192.168.0.1:9999").unwrap();
-    let index = Index::new(root.as_ref(), &addr).unwrap();
+    let index = Index::new(root.as_ref(), &addr, None, &IndexOptions::default()).unwrap();
 
     assert_eq!(index.repository.state(), RepositoryState::Clean);
     assert!(index.repository.head().is_ok());
@@ -435,7 +829,7 @@ mod tests {
     file.write_all(br#"{"dl":"foobar"}"#).unwrap();
 
     let addr = SocketAddr::from_str("254.0.0.0:1").unwrap();
-    let index = Index::new(root.as_ref(), &addr).unwrap();
+    let index = Index::new(root.as_ref(), &addr, None, &IndexOptions::default()).unwrap();
 
     assert_eq!(index.repository.state(), RepositoryState::Clean);
     assert!(index.repository.head().is_ok());
@@ -451,6 +845,158 @@ mod tests {
     assert_eq!(config.api, Some("http://254.0.0.0:1".to_string()));
   }
 
+  /// Check that a public URL is used for the URLs in `config.json`.
+  #[test]
+  fn public_url_config() {
+    let root = tempdir().unwrap();
+    let addr = SocketAddr::from_str("0.0.0.0:8080").unwrap();
+    let url = Some("https://example.com/registry/");
+    let index = Index::new(root.as_ref(), &addr, url, &IndexOptions::default()).unwrap();
+
+    let file = index.root.join("config.json");
+    let config = File::open(file).unwrap();
+    let config = from_reader::<_, Config>(&config).unwrap();
+
+    assert_eq!(
+      config.dl,
+      "https://example.com/registry/api/v1/crates/{crate}/{version}/download"
+    );
+    assert_eq!(config.api, Some("https://example.com/registry".to_string()));
+  }
+
+  /// Check that the `auth-required` flag is reflected in `config.json`.
+  #[test]
+  fn auth_required_config() {
+    let root = tempdir().unwrap();
+    let addr = "127.0.0.1:1".parse().unwrap();
+    let config = root.path().join("config.json");
+
+    let options = IndexOptions {
+      auth_required: true,
+      ..Default::default()
+    };
+    let index = Index::new(root.path(), &addr, None, &options).unwrap();
+    let content = read_to_string(&config).unwrap();
+    assert!(content.contains(r#""auth-required": true"#));
+    drop(index);
+
+    let _index = Index::new(root.path(), &addr, None, &IndexOptions::default()).unwrap();
+    let content = read_to_string(&config).unwrap();
+    assert!(!content.contains("auth-required"));
+  }
+
+  /// Check that commits are authored as configured.
+  #[test]
+  fn git_author_config() {
+    let root = tempdir().unwrap();
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let author = GitAuthor {
+      name: Some("Registry".to_string()),
+      email: Some("registry@example.org".to_string()),
+    };
+    let options = IndexOptions {
+      git_author: author,
+      ..Default::default()
+    };
+    let index = Index::new(root.path(), &addr, None, &options).unwrap();
+
+    let head = index.repository.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.author().name(), Some("Registry"));
+    assert_eq!(head.author().email(), Some("registry@example.org"));
+  }
+
+  /// Check that we generate the information required for serving the
+  /// repository in "dumb" mode.
+  #[test]
+  fn server_info_generation() {
+    let root = tempdir().unwrap();
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let index = Index::new(root.path(), &addr, None, &IndexOptions::default()).unwrap();
+    let git_dir = root.path().join(".git");
+
+    let head = index.repository.head().unwrap();
+    let branch = head.name().unwrap().to_string();
+    let commit = head.peel_to_commit().unwrap();
+    let signature = Signature::now(GIT_USER, GIT_EMAIL).unwrap();
+    let tag = index
+      .repository
+      .tag("v1", commit.as_object(), &signature, "v1", false)
+      .unwrap();
+    let () = create_dir_all(git_dir.join("objects/pack")).unwrap();
+    let () = write(git_dir.join("objects/pack/pack-1234.pack"), b"").unwrap();
+    let () = write(git_dir.join("objects/pack/pack-1234.idx"), b"").unwrap();
+
+    let () = index.update_server_info().unwrap();
+
+    let refs = read_to_string(git_dir.join("info/refs")).unwrap();
+    let expected = format!(
+      "{commit}\t{branch}\n{tag}\trefs/tags/v1\n{commit}\trefs/tags/v1^{{}}\n",
+      commit = commit.id(),
+    );
+    assert_eq!(refs, expected);
+
+    let packs = read_to_string(git_dir.join("objects/info/packs")).unwrap();
+    assert_eq!(packs, "P pack-1234.pack\n\n");
+  }
+
+  /// Check that a failed update restores the previous state.
+  #[test]
+  fn update_rollback() {
+    let root = tempdir().unwrap();
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let mut index = Index::new(root.path(), &addr, None, &IndexOptions::default()).unwrap();
+
+    let () = index
+      .update(&[(Path::new("foo"), b"first")], &[], "Add foo")
+      .unwrap();
+
+    // A regular file can't act as a directory, so writing below it
+    // will fail.
+    let _file = File::create(root.path().join("blocker")).unwrap();
+    let files = [
+      (Path::new("foo"), b"second".as_slice()),
+      (Path::new("bar"), b"bar".as_slice()),
+    ];
+    let untracked = [
+      (Path::new("qux"), b"qux".as_slice()),
+      (Path::new("blocker/baz"), b"baz".as_slice()),
+    ];
+    let _err = index
+      .update(&files, &untracked, "Update files")
+      .unwrap_err();
+
+    assert_eq!(read_to_string(root.path().join("foo")).unwrap(), "first");
+    assert!(!root.path().join("bar").exists());
+    assert!(!root.path().join("qux").exists());
+
+    // Nothing should be staged.
+    let mut options = StatusOptions::new();
+    options.show(StatusShow::Index);
+    let statuses = index.repository.statuses(Some(&mut options)).unwrap();
+    assert_eq!(statuses.len(), 0);
+  }
+
+  /// Check that a failure to update the git server info after the
+  /// commit has been created does not fail the update as a whole.
+  #[test]
+  fn update_server_info_failure() {
+    let root = tempdir().unwrap();
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let mut index = Index::new(root.path(), &addr, None, &IndexOptions::default()).unwrap();
+
+    // A non-empty directory can't be replaced by a file.
+    let info_refs = root.path().join(".git").join("info").join("refs");
+    let () = remove_file(&info_refs).unwrap();
+    let () = create_dir_all(info_refs.join("blocker")).unwrap();
+
+    let () = index
+      .update(&[(Path::new("foo"), b"foo")], &[], "Add foo")
+      .unwrap();
+
+    let head = index.repository.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.message(), Some("Add foo"));
+  }
+
   /// Test that we can create an `Index` in the same registry directory
   /// multiple times without problems.
   #[test]
@@ -459,21 +1005,72 @@ mod tests {
     let addr = "127.0.0.1:0".parse().unwrap();
 
     {
-      let _index = Index::new(root.path(), &addr).unwrap();
+      let _index = Index::new(root.path(), &addr, None, &IndexOptions::default()).unwrap();
     }
 
     {
-      let _index = Index::new(root.path(), &addr).unwrap();
+      let _index = Index::new(root.path(), &addr, None, &IndexOptions::default()).unwrap();
     }
   }
 
+  /// Check that only a single non-read-only `Index` can exist for a
+  /// registry directory at a time.
+  #[test]
+  fn exclusive_index() {
+    let root = tempdir().unwrap();
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let options = IndexOptions::default();
+    let read_only = IndexOptions {
+      read_only: true,
+      ..Default::default()
+    };
+
+    let index = Index::new(root.path(), &addr, None, &options).unwrap();
+    let err = Index::new(root.path(), &addr, None, &options).err().unwrap();
+    assert!(err.to_string().contains("is in use by process"), "{err}");
+
+    let _replica = Index::new(root.path(), &addr, None, &read_only).unwrap();
+
+    drop(index);
+    let _index = Index::new(root.path(), &addr, None, &options).unwrap();
+  }
+
+  /// Check that a read-only `Index` does not modify the registry.
+  #[test]
+  fn read_only_index() {
+    let root = tempdir().unwrap();
+    let addr = "127.0.0.1:1".parse().unwrap();
+    let other = "127.0.0.1:2".parse().unwrap();
+    let read_only = IndexOptions {
+      read_only: true,
+      ..Default::default()
+    };
+
+    // There is nothing to serve if no registry exists yet.
+    assert!(Index::new(root.path(), &addr, None, &read_only).is_err());
+    assert!(!root.path().join(".git").exists());
+
+    let index = Index::new(root.path(), &addr, None, &IndexOptions::default()).unwrap();
+    let head = index.repository.head().unwrap().target().unwrap();
+    let config = read_to_string(root.path().join("config.json")).unwrap();
+    drop(index);
+
+    let options = IndexOptions {
+      auth_required: true,
+      ..read_only
+    };
+    let index = Index::new(root.path(), &other, None, &options).unwrap();
+    assert_eq!(index.repository.head().unwrap().target().unwrap(), head);
+    assert_eq!(read_to_string(root.path().join("config.json")).unwrap(), config);
+  }
+
   /// Check that the Git repository contained in our index has no
   /// untracked files.
   #[test]
   fn no_untracked_files() {
     let root = tempdir().unwrap();
     let addr = "127.0.0.1:0".parse().unwrap();
-    let index = Index::new(root.path(), &addr).unwrap();
+    let index = Index::new(root.path(), &addr, None, &IndexOptions::default()).unwrap();
 
     // The repository should be clean.
     assert_eq!(index.repository.state(), RepositoryState::Clean);
@@ -489,6 +1086,26 @@ mod tests {
     assert_eq!(statuses.len(), 0);
   }
 
+  /// Check that crate meta data files are ignored by git, without
+  /// adding the exclusion more than once.
+  #[test]
+  fn crate_meta_excluded() {
+    let root = tempdir().unwrap();
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let index = Index::new(root.path(), &addr, None, &IndexOptions::default()).unwrap();
+    drop(index);
+    let index = Index::new(root.path(), &addr, None, &IndexOptions::default()).unwrap();
+
+    let () = write(root.path().join("foobar-0.1.0.json"), "{}").unwrap();
+    let mut options = StatusOptions::new();
+    options.include_untracked(true);
+    let statuses = index.repository.statuses(Some(&mut options)).unwrap();
+    assert_eq!(statuses.len(), 0);
+
+    let exclude = read_to_string(root.path().join(".git/info/exclude")).unwrap();
+    assert_eq!(exclude.matches(CRATE_META_PATTERN).count(), 1);
+  }
+
   /// Ensure we can use some special names as relative index root.
   ///
   /// Needs to run in separate process because it changes the working
@@ -504,7 +1121,7 @@ mod tests {
       let relative_index_root = Path::new(special_name);
       let () = create_dir_all(relative_index_root).unwrap();
 
-      let index = Index::new(relative_index_root, &addr).unwrap();
+      let index = Index::new(relative_index_root, &addr, None, &IndexOptions::default()).unwrap();
       // The repository should be clean.
       assert_eq!(index.repository.state(), RepositoryState::Clean);
     }
@@ -5,14 +5,30 @@
 //!
 //! The official reference for registries can be found [here][]. This
 //! crate does not necessarily aim to implement all aspects, as it aims
-//! to be used in trusted contexts where authorization is unnecessary.
+//! to be used in mostly trusted contexts. Token based authentication of
+//! mutating operations is supported, though.
 //!
 //! [here]: https://doc.rust-lang.org/cargo/reference/registries.html
 
 #![allow(clippy::ineffective_open_options)]
 
+mod auth;
 mod index;
+mod lock;
+mod owners;
+mod policy;
 mod publish;
+mod search;
 mod serve;
+mod tarball;
+mod tls;
+mod upload_pack;
+mod yank;
 
+pub use index::GitAuthor;
+pub use policy::ManifestField;
+pub use policy::PublishPolicy;
 pub use serve::serve;
+pub use serve::Options;
+pub use serve::Shutdown;
+pub use tls::TlsConfig;
@@ -0,0 +1,109 @@
+// Copyright (C) 2026 The cargo-http-registry Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::Read as _;
+use std::io::Seek as _;
+use std::io::SeekFrom;
+use std::io::Write as _;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::process;
+
+use anyhow::bail;
+use anyhow::Context as _;
+use anyhow::Result;
+
+use fs2::lock_contended_error;
+use fs2::FileExt as _;
+
+
+/// The path of the lock file relative to the registry root. We keep it
+/// inside the git directory, so that it does not show up as untracked
+/// file in the index.
+const LOCK_FILE_PATH: &str = ".git/registry.lock";
+
+
+/// An exclusive, advisory lock on a registry root.
+///
+/// The lock is held for as long as the object is alive. It is
+/// released by the operating system when the process exits, so that
+/// crashes do not leave behind stale locks.
+#[derive(Debug)]
+pub struct RootLock {
+  /// The locked file. Only ever accessed for keeping it open.
+  _file: File,
+}
+
+impl RootLock {
+  /// Acquire the lock on the registry at `root`, recording our process
+  /// ID and the address we serve on, `addr`, for others to see.
+  pub fn acquire(root: &Path, addr: &SocketAddr) -> Result<Self> {
+    let path = root.join(LOCK_FILE_PATH);
+    // Note that we must not truncate the file before holding the lock,
+    // as it may contain information about the current holder.
+    let mut file = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .create(true)
+      .truncate(false)
+      .open(&path)
+      .with_context(|| format!("failed to open lock file {}", path.display()))?;
+
+    match file.try_lock_exclusive() {
+      Ok(()) => (),
+      Err(err) if err.raw_os_error() == lock_contended_error().raw_os_error() => {
+        let mut holder = String::new();
+        let _result = file.read_to_string(&mut holder);
+        match holder.trim().split_once(' ') {
+          Some((pid, addr)) => bail!(
+            "registry root {} is in use by process {} serving on {}",
+            root.display(),
+            pid,
+            addr
+          ),
+          None => bail!("registry root {} is in use by another process", root.display()),
+        }
+      },
+      Err(err) => {
+        return Err(err).with_context(|| format!("failed to lock {}", path.display()))
+      },
+    }
+
+    let () = file
+      .set_len(0)
+      .and_then(|()| file.seek(SeekFrom::Start(0)).map(|_| ()))
+      .and_then(|()| writeln!(file, "{} {}", process::id(), addr))
+      .with_context(|| format!("failed to write lock file {}", path.display()))?;
+
+    Ok(Self { _file: file })
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::fs::create_dir;
+
+  use tempfile::tempdir;
+
+
+  /// Check that a registry root can only be locked once at a time.
+  #[test]
+  fn exclusive_locking() {
+    let root = tempdir().unwrap();
+    let () = create_dir(root.path().join(".git")).unwrap();
+    let addr = "127.0.0.1:1234".parse().unwrap();
+
+    let lock = RootLock::acquire(root.path(), &addr).unwrap();
+    let err = RootLock::acquire(root.path(), &addr).unwrap_err();
+    let expected = format!("in use by process {} serving on {}", process::id(), addr);
+    assert!(err.to_string().ends_with(&expected), "{err}");
+
+    drop(lock);
+    let _lock = RootLock::acquire(root.path(), &addr).unwrap();
+  }
+}
@@ -4,39 +4,195 @@
 use std::io::stdout;
 use std::io::Write as _;
 use std::net::SocketAddr;
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
 use std::process::exit;
 
+use anyhow::anyhow;
 use anyhow::Context as _;
 use anyhow::Result;
 
 use structopt::StructOpt;
 use tokio::runtime::Builder;
 
+use tracing::error;
+use tracing::info;
 use tracing::subscriber::set_global_default as set_global_subscriber;
 use tracing_subscriber::filter::LevelFilter;
 use tracing_subscriber::fmt::time::SystemTime;
 use tracing_subscriber::FmtSubscriber;
 
 use cargo_http_registry::serve;
+use cargo_http_registry::GitAuthor;
+use cargo_http_registry::ManifestField;
+use cargo_http_registry::Options;
+use cargo_http_registry::PublishPolicy;
+use cargo_http_registry::Shutdown;
+use cargo_http_registry::TlsConfig;
+
+use crate::config::Config;
+
+mod config;
 
 
 /// A struct defining the accepted arguments.
 #[derive(Debug, StructOpt)]
 pub struct Args {
-  /// The root directory of the registry.
+  /// The root directory of the registry. May be omitted if set in the
+  /// configuration file.
   #[structopt(name = "REGISTRY_ROOT", parse(from_os_str))]
-  root: PathBuf,
+  root: Option<PathBuf>,
+  /// A TOML configuration file to use. By default, `registry.toml` in
+  /// the registry root is used, if present. Options provided on the
+  /// command line take precedence over those in the file.
+  #[structopt(short, long, parse(from_os_str))]
+  config: Option<PathBuf>,
   /// The address to serve on. By default we serve on 127.0.0.1 on an
   /// ephemeral port.
-  #[structopt(short, long, default_value = "127.0.0.1:0")]
-  addr: SocketAddr,
+  #[structopt(short, long)]
+  addr: Option<SocketAddr>,
+  /// The URL under which the registry is reachable by clients, if it
+  /// differs from the address served on (e.g., because of a reverse
+  /// proxy). May contain a path prefix, below which all routes are
+  /// served (e.g., `https://example.com/registry`).
+  #[structopt(long)]
+  public_url: Option<String>,
+  /// A PEM file containing the certificate chain to serve HTTPS with.
+  /// The certificate is reloaded on `SIGHUP`.
+  #[structopt(long, requires = "tls-key", parse(from_os_str))]
+  tls_cert: Option<PathBuf>,
+  /// A PEM file containing the private key belonging to the certificate
+  /// provided via `--tls-cert`.
+  #[structopt(long, requires = "tls-cert", parse(from_os_str))]
+  tls_key: Option<PathBuf>,
+  /// The number of threads serving requests. With a single worker (the
+  /// default), everything is served from the main thread.
+  #[structopt(short, long)]
+  workers: Option<NonZeroUsize>,
+  /// A file containing hashed tokens required for publishing and other
+  /// mutating operations. Each line has the form `<user> <sha256>`,
+  /// with `<sha256>` being the hex encoded SHA-256 hash of a token.
+  #[structopt(long, parse(from_os_str))]
+  token_file: Option<PathBuf>,
+  /// Require authentication for read accesses (such as downloads) as
+  /// well. Only supported by cargo when using the sparse protocol.
+  #[structopt(long, overrides_with = "no-auth-required")]
+  auth_required: bool,
+  /// Do not require authentication for read accesses, overriding the
+  /// configuration file.
+  #[structopt(long, overrides_with = "auth-required")]
+  no_auth_required: bool,
+  /// Serve an existing registry read-only, rejecting publishes and
+  /// other modifications and leaving the registry untouched. Without
+  /// this flag, the registry root is locked, so that only a single
+  /// process can serve it. Read-only replicas do not take the lock.
+  #[structopt(long, overrides_with = "no-read-only")]
+  read_only: bool,
+  /// Serve the registry read-write, overriding the configuration file.
+  #[structopt(long, overrides_with = "read-only")]
+  no_read_only: bool,
+  /// Check that normal and build dependencies of published crates can
+  /// be resolved. Dependencies from this registry need to have a
+  /// matching non-yanked version, while dependencies from other
+  /// registries are only accepted if the registry was allowed via
+  /// `--allowed-registry`.
+  #[structopt(long, overrides_with = "no-check-dependencies")]
+  check_dependencies: bool,
+  /// Do not check dependencies of published crates, overriding the
+  /// configuration file.
+  #[structopt(long, overrides_with = "check-dependencies")]
+  no_check_dependencies: bool,
+  /// The index URL of a registry that dependencies of published crates
+  /// may come from (can be supplied multiple times). Only relevant in
+  /// conjunction with `--check-dependencies`.
+  #[structopt(long = "allowed-registry", number_of_values = 1)]
+  allowed_registries: Vec<String>,
+  /// The maximum size of a published `.crate` file, in bytes. Defaults
+  /// to 20 MiB.
+  #[structopt(long)]
+  max_crate_size: Option<u64>,
+  /// The maximum size of the unpacked contents of a published `.crate`
+  /// file, in bytes. Defaults to 512 MiB.
+  #[structopt(long)]
+  max_unpacked_size: Option<u64>,
+  /// A manifest field that published crates have to specify (can be
+  /// supplied multiple times). One of `description`, `documentation`,
+  /// `homepage`, `license`, `readme`, or `repository`.
+  #[structopt(long = "require-field", number_of_values = 1)]
+  required_fields: Vec<ManifestField>,
+  /// An SPDX license identifier that published crates may use (can be
+  /// supplied multiple times). By default, all licenses are accepted.
+  #[structopt(long = "allowed-license", number_of_values = 1)]
+  allowed_licenses: Vec<String>,
   /// Increase verbosity (can be supplied multiple times).
   #[structopt(short = "v", long = "verbose", global = true, parse(from_occurrences))]
   verbosity: usize,
 }
 
 
+/// Pick a list provided on the command line if non-empty, falling back
+/// to the one from the configuration file otherwise.
+fn or_config<T>(args: Vec<T>, config: Vec<T>) -> Vec<T> {
+  if args.is_empty() {
+    config
+  } else {
+    args
+  }
+}
+
+/// Pick the value of a boolean setting, honoring the `--<flag>` and
+/// `--no-<flag>` forms provided on the command line (`enable` and
+/// `disable`, respectively) over the one from the configuration file.
+fn flag_or_config(enable: bool, disable: bool, config: bool) -> bool {
+  if enable {
+    true
+  } else if disable {
+    false
+  } else {
+    config
+  }
+}
+
+
+/// Wait for a request to terminate, in the form of `SIGINT` or
+/// `SIGTERM`.
+#[cfg(unix)]
+async fn terminated() {
+  use std::future::poll_fn;
+  use std::task::Poll;
+
+  use tokio::signal::unix::signal;
+  use tokio::signal::unix::SignalKind;
+
+  let (mut interrupt, mut terminate) =
+    match (signal(SignalKind::interrupt()), signal(SignalKind::terminate())) {
+      (Ok(interrupt), Ok(terminate)) => (interrupt, terminate),
+      (Err(err), _) | (_, Err(err)) => {
+        error!("failed to install signal handlers: {}", err);
+        return std::future::pending().await
+      },
+    };
+
+  poll_fn(|cx| {
+    if interrupt.poll_recv(cx).is_ready() || terminate.poll_recv(cx).is_ready() {
+      Poll::Ready(())
+    } else {
+      Poll::Pending
+    }
+  })
+  .await
+}
+
+/// Wait for a request to terminate, in the form of Ctrl-C.
+#[cfg(not(unix))]
+async fn terminated() {
+  if let Err(err) = tokio::signal::ctrl_c().await {
+    error!("failed to install Ctrl-C handler: {}", err);
+    std::future::pending().await
+  }
+}
+
+
 fn run() -> Result<()> {
   let args = Args::from_args_safe()?;
   let level = match args.verbosity {
@@ -53,10 +209,83 @@ fn run() -> Result<()> {
 
   set_global_subscriber(subscriber).context("failed to set tracing subscriber")?;
 
-  let rt = Builder::new_current_thread().enable_io().build().unwrap();
+  let config = Config::find(args.config.as_deref(), args.root.as_deref())?;
+  let root = args
+    .root
+    .or(config.root)
+    .ok_or_else(|| anyhow!("no registry root provided on the command line or in configuration"))?;
+  let addr = args
+    .addr
+    .or(config.listen_addr)
+    .unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 0)));
+
+  let tls = match (args.tls_cert, args.tls_key) {
+    (Some(cert), Some(key)) => Some(TlsConfig { cert, key }),
+    _ => config.tls.map(|tls| TlsConfig {
+      cert: tls.cert,
+      key: tls.key,
+    }),
+  };
+
+  let default_policy = PublishPolicy::default();
+  let options = Options {
+    token_file: args.token_file.or(config.token_file),
+    auth_required: flag_or_config(
+      args.auth_required,
+      args.no_auth_required,
+      config.auth_required,
+    ),
+    publish_policy: PublishPolicy {
+      max_crate_size: args
+        .max_crate_size
+        .or(config.publish.max_crate_size)
+        .unwrap_or(default_policy.max_crate_size),
+      max_unpacked_size: args
+        .max_unpacked_size
+        .or(config.publish.max_unpacked_size)
+        .unwrap_or(default_policy.max_unpacked_size),
+      required_fields: or_config(args.required_fields, config.publish.required_fields),
+      allowed_licenses: or_config(args.allowed_licenses, config.publish.allowed_licenses),
+      check_dependencies: flag_or_config(
+        args.check_dependencies,
+        args.no_check_dependencies,
+        config.publish.check_dependencies,
+      ),
+      allowed_registries: or_config(args.allowed_registries, config.publish.allowed_registries),
+    },
+    git_author: GitAuthor {
+      name: config.git.name,
+      email: config.git.email,
+    },
+    public_url: args.public_url.or(config.public_url),
+    tls,
+    shutdown: Shutdown::default(),
+    read_only: flag_or_config(args.read_only, args.no_read_only, config.read_only),
+  };
+  let shutdown = options.shutdown.clone();
+
+  let workers = args.workers.or(config.workers).map_or(1, NonZeroUsize::get);
+  let rt = if workers > 1 {
+    Builder::new_multi_thread()
+      .worker_threads(workers)
+      .enable_io()
+      .enable_time()
+      .build()
+  } else {
+    Builder::new_current_thread()
+      .enable_io()
+      .enable_time()
+      .build()
+  }
+  .context("failed to create tokio runtime")?;
   let _guard = rt.enter();
 
-  let (serve, _addr) = serve(&args.root, args.addr)?;
+  let (serve, _addr) = serve(&root, addr, options)?;
+  let _handle = rt.spawn(async move {
+    let () = terminated().await;
+    info!("shutting down");
+    shutdown.trigger()
+  });
   rt.block_on(serve);
   Ok(())
 }
@@ -0,0 +1,225 @@
+// Copyright (C) 2026 The cargo-http-registry Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fs::read_to_string;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::bail;
+use anyhow::ensure;
+use anyhow::Context as _;
+use anyhow::Result;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::auth::Auth;
+use crate::index::read_entries;
+use crate::index::Index;
+use crate::publish::check_crate_name;
+use crate::publish::crate_index_path;
+
+
+/// A user as reported by the owners endpoint.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct User {
+  /// A numeric ID of the user. We have no concept of such IDs and just
+  /// enumerate the owners.
+  id: usize,
+  /// The login name of the user.
+  login: String,
+  /// The full name of the user, which we do not know.
+  name: Option<String>,
+}
+
+/// The response to a request listing the owners of a crate.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Owners {
+  users: Vec<User>,
+}
+
+/// The body of a request adding or removing owners.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct OwnersChange {
+  users: Vec<String>,
+}
+
+/// The response to a request adding or removing owners.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct OwnersChangeResult {
+  ok: bool,
+  msg: String,
+}
+
+
+/// Craft the path, relative to the registry root, of the file
+/// containing the owners of crate `name`.
+pub fn owners_file_path(name: &str) -> PathBuf {
+  PathBuf::from(format!("{}.owners", name.to_ascii_lowercase()))
+}
+
+/// Read the owners of crate `name` from the registry at `root`.
+///
+/// Each line of an owners file contains the name of a single user. A
+/// crate without an owners file has no owners.
+pub fn read_owners(root: &Path, name: &str) -> Result<Vec<String>> {
+  let path = root.join(owners_file_path(name));
+  let content = match read_to_string(&path) {
+    Ok(content) => content,
+    Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+    Err(err) => {
+      return Err(err).with_context(|| format!("failed to read owners file {}", path.display()))
+    },
+  };
+
+  let owners = content
+    .lines()
+    .map(str::trim)
+    .filter(|line| !line.is_empty())
+    .map(str::to_string)
+    .collect();
+  Ok(owners)
+}
+
+/// Format a list of owners as the contents of an owners file.
+pub fn format_owners(owners: &[String]) -> String {
+  owners
+    .iter()
+    .map(|owner| format!("{}\n", owner))
+    .collect()
+}
+
+/// Check that `user` is allowed to modify crate `name`.
+///
+/// Without authentication (`user` being `None`) everybody is
+/// permitted. The same is true for crates that do not have any owners
+/// recorded.
+pub fn check_owner(root: &Path, name: &str, user: Option<&str>) -> Result<()> {
+  if let Some(user) = user {
+    let owners = read_owners(root, name)?;
+    ensure!(
+      owners.is_empty() || owners.iter().any(|owner| owner == user),
+      "user {} is not an owner of crate {}",
+      user,
+      name
+    );
+  }
+  Ok(())
+}
+
+
+/// Handler for `GET` requests to the `/api/v1/crates/{name}/owners`
+/// endpoint.
+pub fn list_owners(root: &Path, name: &str) -> Result<Owners> {
+  let () = check_crate_name(name)?;
+
+  let index_path = root.join(crate_index_path(name));
+  let entries = read_entries(&index_path)?;
+  ensure!(!entries.is_empty(), "crate {} does not exist", name);
+
+  let users = read_owners(root, name)?
+    .into_iter()
+    .enumerate()
+    .map(|(id, login)| User {
+      id: id + 1,
+      login,
+      name: None,
+    })
+    .collect();
+  Ok(Owners { users })
+}
+
+/// Handler for `PUT` and `DELETE` requests to the
+/// `/api/v1/crates/{name}/owners` endpoint.
+///
+/// Add the users in `change` as owners of crate `name` if `add` is
+/// `true`, remove them otherwise.
+pub fn change_owners(
+  index: &mut Index,
+  auth: &Auth,
+  user: Option<&str>,
+  name: &str,
+  change: OwnersChange,
+  add: bool,
+) -> Result<OwnersChangeResult> {
+  let () = check_crate_name(name)?;
+
+  let user = match user {
+    Some(user) => user,
+    None => bail!("managing crate owners requires authentication to be enabled"),
+  };
+
+  let index_path = index.root().join(crate_index_path(name));
+  let entries = read_entries(&index_path)?;
+  ensure!(!entries.is_empty(), "crate {} does not exist", name);
+
+  let () = check_owner(index.root(), name, Some(user))?;
+
+  let mut owners = read_owners(index.root(), name)?;
+  if add {
+    for login in &change.users {
+      ensure!(auth.has_user(login), "user {} is not known", login);
+      if !owners.contains(login) {
+        owners.push(login.clone());
+      }
+    }
+  } else {
+    owners.retain(|owner| !change.users.contains(owner));
+    ensure!(
+      !owners.is_empty(),
+      "cannot remove all owners of crate {}",
+      name
+    );
+  }
+
+  let relative_path = owners_file_path(name);
+  let content = format_owners(&owners);
+  index
+    .update(
+      &[(&relative_path, content.as_bytes())],
+      &[],
+      &format!("Update owners of {}", name),
+    )
+    .context("failed to commit changes to index")?;
+
+  let users = change.users.join(", ");
+  let msg = if add {
+    format!("user(s) {} added as owner(s) of crate {}", users, name)
+  } else {
+    format!("user(s) {} removed as owner(s) of crate {}", users, name)
+  };
+
+  let result = OwnersChangeResult { ok: true, msg };
+  Ok(result)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::fs::write;
+
+  use tempfile::tempdir;
+
+
+  /// Check that we can read and write owners files.
+  #[test]
+  fn owners_roundtrip() {
+    let root = tempdir().unwrap();
+    assert!(read_owners(root.path(), "foobar").unwrap().is_empty());
+    let () = check_owner(root.path(), "foobar", Some("alice")).unwrap();
+
+    let owners = vec!["alice".to_string(), "bob".to_string()];
+    let path = root.path().join(owners_file_path("foobar"));
+    assert_eq!(path, root.path().join("foobar.owners"));
+    let () = write(&path, format_owners(&owners)).unwrap();
+    assert_eq!(read_owners(root.path(), "foobar").unwrap(), owners);
+
+    let () = check_owner(root.path(), "foobar", None).unwrap();
+    let () = check_owner(root.path(), "foobar", Some("bob")).unwrap();
+    let err = check_owner(root.path(), "foobar", Some("eve")).unwrap_err();
+    assert_eq!(err.to_string(), "user eve is not an owner of crate foobar");
+  }
+}
@@ -0,0 +1,239 @@
+// Copyright (C) 2026 The cargo-http-registry Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::error::Error as StdError;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fmt::Result as FmtResult;
+use std::iter::Peekable;
+use std::str::FromStr;
+
+use anyhow::bail;
+use anyhow::Error;
+
+use serde::Deserialize;
+
+
+/// The default maximum size of a `.crate` file, in bytes.
+const DEFAULT_MAX_CRATE_SIZE: u64 = 20 * 1024 * 1024;
+/// The default maximum size of the unpacked contents of a `.crate` file,
+/// in bytes. This is the same limit crates.io employs.
+const DEFAULT_MAX_UNPACKED_SIZE: u64 = 512 * 1024 * 1024;
+
+
+/// A field of a crate's manifest that can be required to be present.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ManifestField {
+  /// The `description` field.
+  Description,
+  /// The `documentation` field.
+  Documentation,
+  /// The `homepage` field.
+  Homepage,
+  /// The `license` field. A `license-file` satisfies this requirement,
+  /// too.
+  License,
+  /// The `readme` field.
+  Readme,
+  /// The `repository` field.
+  Repository,
+}
+
+impl Display for ManifestField {
+  fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+    let s = match self {
+      Self::Description => "description",
+      Self::Documentation => "documentation",
+      Self::Homepage => "homepage",
+      Self::License => "license",
+      Self::Readme => "readme",
+      Self::Repository => "repository",
+    };
+    f.write_str(s)
+  }
+}
+
+impl FromStr for ManifestField {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let field = match s {
+      "description" => Self::Description,
+      "documentation" => Self::Documentation,
+      "homepage" => Self::Homepage,
+      "license" => Self::License,
+      "readme" => Self::Readme,
+      "repository" => Self::Repository,
+      _ => bail!("unsupported manifest field: {}", s),
+    };
+    Ok(field)
+  }
+}
+
+
+/// Policy governing which crates may be published.
+#[derive(Clone, Debug)]
+pub struct PublishPolicy {
+  /// The maximum size of a `.crate` file, in bytes.
+  pub max_crate_size: u64,
+  /// The maximum size of the unpacked contents of a `.crate` file, in
+  /// bytes.
+  pub max_unpacked_size: u64,
+  /// Manifest fields that published crates have to specify.
+  pub required_fields: Vec<ManifestField>,
+  /// The SPDX license identifiers crates may be published under. If
+  /// empty, all licenses are permitted.
+  pub allowed_licenses: Vec<String>,
+  /// Whether to check that normal and build dependencies can be
+  /// resolved. Dependencies from this registry need to have at least
+  /// one non-yanked version matching their requirement, while those
+  /// from other registries need to have their registry listed in
+  /// `allowed_registries`.
+  pub check_dependencies: bool,
+  /// The index URLs of registries that dependencies may come from, if
+  /// `check_dependencies` is set.
+  pub allowed_registries: Vec<String>,
+}
+
+impl Default for PublishPolicy {
+  fn default() -> Self {
+    Self {
+      max_crate_size: DEFAULT_MAX_CRATE_SIZE,
+      max_unpacked_size: DEFAULT_MAX_UNPACKED_SIZE,
+      required_fields: Vec::new(),
+      allowed_licenses: Vec::new(),
+      check_dependencies: false,
+      allowed_registries: Vec::new(),
+    }
+  }
+}
+
+
+/// An error representing one or more violations of the publish policy.
+///
+/// Each violation is reported to the client separately.
+#[derive(Debug)]
+pub struct PolicyViolations(pub Vec<String>);
+
+impl Display for PolicyViolations {
+  fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+    f.write_str(&self.0.join("; "))
+  }
+}
+
+impl StdError for PolicyViolations {}
+
+
+/// Evaluate a sequence of SPDX license expression tokens.
+///
+/// The grammar we support is:
+/// ```text
+/// or   := and ("OR" and)*
+/// and  := atom ("AND" atom)*
+/// atom := "(" or ")" | license ["WITH" exception]
+/// ```
+/// The legacy `/` separator is treated as `OR`.
+struct LicenseExpr<'s, I>
+where
+  I: Iterator<Item = &'s str>,
+{
+  tokens: Peekable<I>,
+  allowed: &'s [String],
+}
+
+impl<'s, I> LicenseExpr<'s, I>
+where
+  I: Iterator<Item = &'s str>,
+{
+  fn or(&mut self) -> Option<bool> {
+    let mut result = self.and()?;
+    while matches!(self.tokens.peek(), Some(&"OR") | Some(&"/")) {
+      let _op = self.tokens.next();
+      let other = self.and()?;
+      result = result || other;
+    }
+    Some(result)
+  }
+
+  fn and(&mut self) -> Option<bool> {
+    let mut result = self.atom()?;
+    while self.tokens.peek() == Some(&"AND") {
+      let _op = self.tokens.next();
+      let other = self.atom()?;
+      result = result && other;
+    }
+    Some(result)
+  }
+
+  fn atom(&mut self) -> Option<bool> {
+    match self.tokens.next()? {
+      "(" => {
+        let result = self.or()?;
+        (self.tokens.next()? == ")").then_some(result)
+      },
+      ")" | "AND" | "OR" | "WITH" | "/" => None,
+      license => {
+        if self.tokens.peek() == Some(&"WITH") {
+          let _with = self.tokens.next();
+          let _exception = self.tokens.next()?;
+        }
+        Some(self.allowed.iter().any(|allowed| allowed == license))
+      },
+    }
+  }
+}
+
+
+/// Check whether the SPDX license expression `license` is satisfied by
+/// the given set of allowed licenses.
+///
+/// Malformed expressions are never permitted.
+pub fn is_license_allowed(license: &str, allowed: &[String]) -> bool {
+  let license = license
+    .replace('(', " ( ")
+    .replace(')', " ) ")
+    .replace('/', " / ");
+  let mut expr = LicenseExpr {
+    tokens: license.split_whitespace().peekable(),
+    allowed,
+  };
+  matches!(expr.or(), Some(true)) && expr.tokens.next().is_none()
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  /// Check that we evaluate license expressions correctly.
+  #[test]
+  fn license_expressions() {
+    let allowed = ["MIT".to_string(), "Apache-2.0".to_string()];
+
+    assert!(is_license_allowed("MIT", &allowed));
+    assert!(is_license_allowed("MIT OR GPL-3.0", &allowed));
+    assert!(is_license_allowed("MIT/GPL-3.0", &allowed));
+    assert!(is_license_allowed("MIT AND Apache-2.0", &allowed));
+    assert!(is_license_allowed("(MIT OR GPL-3.0) AND Apache-2.0", &allowed));
+    assert!(is_license_allowed("Apache-2.0 WITH LLVM-exception", &allowed));
+
+    assert!(!is_license_allowed("GPL-3.0", &allowed));
+    assert!(!is_license_allowed("MIT AND GPL-3.0", &allowed));
+    assert!(!is_license_allowed("(MIT OR GPL-3.0) AND BSD-3-Clause", &allowed));
+    assert!(!is_license_allowed("", &allowed));
+    assert!(!is_license_allowed("(MIT", &allowed));
+    assert!(!is_license_allowed("MIT OR", &allowed));
+    assert!(!is_license_allowed("MIT Apache-2.0", &allowed));
+  }
+
+  /// Check that we can parse manifest fields.
+  #[test]
+  fn manifest_field_parsing() {
+    for field in ["description", "license", "repository"] {
+      assert_eq!(ManifestField::from_str(field).unwrap().to_string(), field);
+    }
+    assert!(ManifestField::from_str("foobar").is_err());
+  }
+}
@@ -6,33 +6,61 @@ use std::convert::TryInto as _;
 use std::fmt::Display;
 use std::fmt::Formatter;
 use std::fmt::Result as FmtResult;
-use std::fs::create_dir_all;
-use std::fs::OpenOptions;
-use std::io::Write as _;
+use std::fs::read;
+use std::fs::File;
+use std::io::ErrorKind;
 use std::mem::size_of;
 use std::ops::Deref as _;
+use std::path::Path;
 use std::path::PathBuf;
 use std::slice::from_ref as slice_from_ref;
 use std::str::from_utf8 as str_from_utf8;
 
+use anyhow::bail;
 use anyhow::ensure;
 use anyhow::Context as _;
 use anyhow::Result;
 
+use semver::Version;
+use semver::VersionReq;
+
 use sha2::Digest as _;
 use sha2::Sha256;
 
 use serde::Deserialize;
 use serde::Serialize;
+use serde_json::from_reader;
 use serde_json::from_slice;
+use serde_json::to_vec_pretty;
 use serde_json::to_writer;
 
 use tracing::warn;
 
 use warp::hyper::body::Bytes;
 
+use crate::index::read_entries;
 use crate::index::Entry;
 use crate::index::Index;
+use crate::owners::check_owner;
+use crate::owners::format_owners;
+use crate::owners::owners_file_path;
+use crate::owners::read_owners;
+use crate::policy::is_license_allowed;
+use crate::policy::ManifestField;
+use crate::policy::PolicyViolations;
+use crate::policy::PublishPolicy;
+use crate::search::crate_names;
+use crate::tarball::check_crate_tarball;
+
+/// The maximum length of a crate name, as enforced by crates.io.
+const MAX_CRATE_NAME_LENGTH: usize = 64;
+/// Crate names we do not permit, because they are reserved file names
+/// on Windows.
+const RESERVED_NAMES: [&str; 22] = [
+  "con", "prn", "aux", "nul", "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8",
+  "com9", "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9",
+];
+
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
@@ -102,7 +130,6 @@ impl From<Dep> for crate::index::Dep {
 }
 
 #[derive(Debug, Deserialize)]
-#[allow(unused)]
 struct MetaData {
   /// The name of the package.
   name: String,
@@ -152,11 +179,90 @@ struct MetaData {
   /// The `links` string value from the package's manifest, or null if
   /// not specified. This field is optional and defaults to null.
   links: Option<String>,
+  /// The minimum supported Rust version of the package, if specified.
+  #[serde(default)]
+  rust_version: Option<String>,
+}
+
+/// Descriptive meta data about a published crate version.
+///
+/// This data is not part of the index proper, but stored in a separate
+/// file next to the `.crate` file.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct CrateMeta {
+  /// The name of the package.
+  pub name: String,
+  /// The version of the package.
+  pub vers: String,
+  /// List of strings of the authors.
+  pub authors: Vec<String>,
+  /// Description field from the manifest.
+  pub description: Option<String>,
+  /// String of the URL to the website for this package's documentation.
+  pub documentation: Option<String>,
+  /// String of the URL to the website for this package's home page.
+  pub homepage: Option<String>,
+  /// String of the content of the README file.
+  pub readme: Option<String>,
+  /// String of a relative path to a README file in the crate.
+  pub readme_file: Option<String>,
+  /// Array of strings of keywords for the package.
+  pub keywords: Vec<String>,
+  /// Array of strings of categories for the package.
+  pub categories: Vec<String>,
+  /// String of the license for the package.
+  pub license: Option<String>,
+  /// String of a relative path to a license file in the crate.
+  pub license_file: Option<String>,
+  /// String of the URL to the website for the source repository of this
+  /// package.
+  pub repository: Option<String>,
+  /// Object of "status" badges.
+  pub badges: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl From<&MetaData> for CrateMeta {
+  fn from(source: &MetaData) -> Self {
+    Self {
+      name: source.name.clone(),
+      vers: source.vers.clone(),
+      authors: source.authors.clone(),
+      description: source.description.clone(),
+      documentation: source.documentation.clone(),
+      homepage: source.homepage.clone(),
+      readme: source.readme.clone(),
+      readme_file: source.readme_file.clone(),
+      keywords: source.keywords.clone(),
+      categories: source.categories.clone(),
+      license: source.license.clone(),
+      license_file: source.license_file.clone(),
+      repository: source.repository.clone(),
+      badges: source.badges.clone(),
+    }
+  }
+}
+
+/// Check whether a feature's value makes use of syntax introduced with
+/// version 2 of the index format, i.e., `dep:` dependencies or weak
+/// dependency features (`foo?/bar`).
+fn is_features2_value(value: &str) -> bool {
+  value.starts_with("dep:") || value.contains("?/")
 }
 
 impl From<(MetaData, &[u8])> for Entry {
   fn from(source: (MetaData, &[u8])) -> Self {
     let (metadata, data) = source;
+    let (features2, features) = metadata
+      .features
+      .into_iter()
+      .partition::<BTreeMap<_, _>, _>(|(_, values)| {
+        values.iter().any(|value| is_features2_value(value))
+      });
+    let (features2, v) = if features2.is_empty() {
+      (None, None)
+    } else {
+      (Some(features2), Some(2))
+    };
 
     Self {
       name: metadata.name,
@@ -167,16 +273,102 @@ impl From<(MetaData, &[u8])> for Entry {
         .map(crate::index::Dep::from)
         .collect(),
       cksum: format!("{:x}", Sha256::digest(data)),
-      features: metadata.features,
+      features,
       yanked: false,
       links: metadata.links,
+      features2,
+      v,
+      rust_version: metadata.rust_version,
     }
   }
 }
 
 /// Craft the file name for a crate named `name` in version `version`.
 pub fn crate_file_name(name: &str, version: &str) -> String {
-  format!("{}-{}.crate", name, version)
+  format!("{}-{}.crate", name.to_ascii_lowercase(), version)
+}
+
+/// Craft the name of the file containing the `CrateMeta` of a crate
+/// named `name` in version `version`.
+pub fn crate_meta_file_name(name: &str, version: &str) -> String {
+  format!("{}-{}.json", name.to_ascii_lowercase(), version)
+}
+
+/// Normalize a crate name for the purpose of comparing it to others.
+///
+/// Just like crates.io we treat names differing only in case or in
+/// the use of `-` vs. `_` as equal.
+pub fn normalize_crate_name(name: &str) -> String {
+  name.to_ascii_lowercase().replace('_', "-")
+}
+
+/// Read the `CrateMeta` of crate `name` in version `version` from the
+/// registry at `root`.
+///
+/// `None` is returned for crates published before such meta data were
+/// recorded.
+pub fn read_crate_meta(root: &Path, name: &str, version: &str) -> Result<Option<CrateMeta>> {
+  let path = root.join(crate_meta_file_name(name, version));
+  let file = match File::open(&path) {
+    Ok(file) => file,
+    Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+    Err(err) => {
+      return Err(err).with_context(|| format!("failed to open {}", path.display()))
+    },
+  };
+
+  let meta = from_reader::<_, CrateMeta>(file)
+    .with_context(|| format!("failed to parse crate meta data in {}", path.display()))?;
+  Ok(Some(meta))
+}
+
+/// Check that the given crate name is acceptable.
+///
+/// We follow the rules of cargo and crates.io: names consist of ASCII
+/// alphanumeric characters, `-`, and `_`, start with a letter, are at
+/// most 64 characters long, and must not be a reserved file name on
+/// Windows.
+pub fn check_crate_name(name: &str) -> Result<()> {
+  ensure!(!name.is_empty(), "crate name cannot be empty");
+  ensure!(name.is_ascii(), "crate name contains non-ASCII characters");
+  ensure!(
+    name.len() <= MAX_CRATE_NAME_LENGTH,
+    "crate name {} exceeds maximum length of {} characters",
+    name,
+    MAX_CRATE_NAME_LENGTH
+  );
+  ensure!(
+    name.as_bytes()[0].is_ascii_alphabetic(),
+    "crate name {} does not start with a letter",
+    name
+  );
+  ensure!(
+    name
+      .bytes()
+      .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_'),
+    "crate name {} contains invalid characters; only alphanumeric characters, `-`, and `_` are allowed",
+    name
+  );
+
+  let lower = name.to_ascii_lowercase();
+  ensure!(
+    !RESERVED_NAMES.contains(&lower.as_str()),
+    "crate name {} is reserved",
+    name
+  );
+  Ok(())
+}
+
+/// Check that the given crate version is a valid semantic version.
+pub fn check_crate_version(version: &str) -> Result<()> {
+  let _version = Version::parse(version)
+    .with_context(|| format!("crate version {} is not a valid semantic version", version))?;
+  Ok(())
+}
+
+/// Check whether two versions are the same, ignoring build metadata.
+fn is_same_version(a: &Version, b: &Version) -> bool {
+  (a.major, a.minor, a.patch, &a.pre) == (b.major, b.minor, b.patch, &b.pre)
 }
 
 /// Extract and parse a `u32` value from a `Bytes` object.
@@ -200,9 +392,12 @@ fn parse_metadata(bytes: &mut Bytes, json_length: usize) -> Result<MetaData> {
 }
 
 /// Infer the path to a crate inside the index from its name.
-fn crate_path(name: &str) -> PathBuf {
+///
+/// Similar to crates.io, we use lower case paths only.
+pub fn crate_path(name: &str) -> PathBuf {
   // Should have been verified already at this point.
   debug_assert!(name.is_ascii());
+  let name = name.to_ascii_lowercase();
 
   fn to_str(c: &u8) -> &str {
     str_from_utf8(slice_from_ref(c)).unwrap()
@@ -222,6 +417,124 @@ fn crate_path(name: &str) -> PathBuf {
   }
 }
 
+/// Infer the path to a crate's index file, relative to the index root,
+/// from the crate's name.
+pub fn crate_index_path(name: &str) -> PathBuf {
+  crate_path(name).join(name.to_ascii_lowercase())
+}
+
+/// Check whether `path`, relative to the index root and using forward
+/// slashes as separators, refers to a crate's index file.
+pub fn is_crate_index_path(path: &str) -> bool {
+  // `rsplit` always yields at least one element.
+  let name = path.rsplit('/').next().unwrap();
+  let valid = !name.is_empty()
+    && name
+      .bytes()
+      .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_');
+
+  valid && crate_index_path(name) == Path::new(path)
+}
+
+/// Check that the given dependencies can be resolved, as per `policy`.
+fn check_dependencies(root: &Path, deps: &[Dep], policy: &PublishPolicy) -> Result<()> {
+  if !policy.check_dependencies {
+    return Ok(())
+  }
+
+  for dep in deps {
+    if let Kind::Dev = dep.kind {
+      continue
+    }
+
+    if let Some(registry) = &dep.registry {
+      ensure!(
+        policy.allowed_registries.contains(registry),
+        "dependency {} is from registry {}, which is not allowed",
+        dep.name,
+        registry
+      );
+      continue
+    }
+
+    let req = VersionReq::parse(&dep.version_req).with_context(|| {
+      format!(
+        "dependency {} has invalid version requirement {}",
+        dep.name, dep.version_req
+      )
+    })?;
+    let () = check_crate_name(&dep.name)?;
+    let entries = read_entries(&root.join(crate_index_path(&dep.name)))?;
+    let satisfied = entries.iter().any(|entry| {
+      !entry.yanked
+        && Version::parse(&entry.vers)
+          .map(|vers| req.matches(&vers))
+          .unwrap_or(false)
+    });
+    ensure!(
+      satisfied,
+      "dependency {} ({}) is not satisfied by any non-yanked version in this registry",
+      dep.name,
+      dep.version_req
+    );
+  }
+  Ok(())
+}
+
+/// Check the meta data and size of a crate against `policy`.
+///
+/// All violations found are reported at once.
+fn check_policy(metadata: &MetaData, crate_length: u64, policy: &PublishPolicy) -> Result<()> {
+  let mut violations = Vec::new();
+
+  if crate_length > policy.max_crate_size {
+    violations.push(format!(
+      "crate file size of {} bytes exceeds maximum of {} bytes",
+      crate_length, policy.max_crate_size
+    ));
+  }
+
+  let present = |value: &Option<String>| {
+    value
+      .as_deref()
+      .map(|value| !value.trim().is_empty())
+      .unwrap_or(false)
+  };
+  for field in &policy.required_fields {
+    let satisfied = match field {
+      ManifestField::Description => present(&metadata.description),
+      ManifestField::Documentation => present(&metadata.documentation),
+      ManifestField::Homepage => present(&metadata.homepage),
+      ManifestField::License => present(&metadata.license) || present(&metadata.license_file),
+      ManifestField::Readme => present(&metadata.readme) || present(&metadata.readme_file),
+      ManifestField::Repository => present(&metadata.repository),
+    };
+    if !satisfied {
+      violations.push(format!("manifest field `{}` is required", field));
+    }
+  }
+
+  if !policy.allowed_licenses.is_empty() {
+    let allowed = policy.allowed_licenses.join(", ");
+    match &metadata.license {
+      Some(license) if is_license_allowed(license, &policy.allowed_licenses) => (),
+      Some(license) => violations.push(format!(
+        "license `{}` is not allowed; allowed licenses are: {}",
+        license, allowed
+      )),
+      None => violations.push(format!(
+        "crate has to specify a license; allowed licenses are: {}",
+        allowed
+      )),
+    }
+  }
+
+  if !violations.is_empty() {
+    return Err(PolicyViolations(violations).into())
+  }
+  Ok(())
+}
+
 /// Read the actual crate data from the request.
 fn read_crate(bytes: &mut Bytes, crate_length: usize) -> Result<Bytes> {
   ensure!(bytes.len() >= crate_length, "not enough data for crate");
@@ -231,13 +544,23 @@ fn read_crate(bytes: &mut Bytes, crate_length: usize) -> Result<Bytes> {
 }
 
 /// PUT handler for the `/api/v1/crates/new` endpoint.
-// TODO: We may want to rollback earlier changes if we error out
-//       somewhere in the middle.
+///
+/// `user` is the name of the authenticated user publishing the crate,
+/// if authentication is enabled. Only owners of a crate may publish new
+/// versions of it and the first publisher becomes its owner.
+///
+/// Publishing is transactional: if anything goes wrong, the registry is
+/// left in the state it was in before.
 // Note that in here we leak paths in errors. Right now that's by
 // design, but if we ever were to change our security model and assume
 // bad-faith actors attempting to publish and do other things, that may
 // not be so wise.
-pub fn publish_crate(mut body: Bytes, index: &mut Index) -> Result<()> {
+pub fn publish_crate(
+  mut body: Bytes,
+  index: &mut Index,
+  user: Option<&str>,
+  policy: &PublishPolicy,
+) -> Result<()> {
   let json_length = parse_u32(&mut body)
     .context("failed to read JSON length")?
     .try_into()
@@ -247,73 +570,119 @@ pub fn publish_crate(mut body: Bytes, index: &mut Index) -> Result<()> {
   let crate_name = metadata.name.clone();
   let crate_vers = metadata.vers.clone();
 
-  // TODO: Strictly speaking we should have more checks in place here.
-  ensure!(!crate_name.is_empty(), "crate name cannot be empty");
-  ensure!(
-    crate_name.is_ascii(),
-    "crate name contains non-ASCII characters"
-  );
-
-  let crate_meta_relative_dir = crate_path(&crate_name);
-  let crate_meta_dir = index.root().join(&crate_meta_relative_dir);
-  create_dir_all(&crate_meta_dir)
-    .with_context(|| format!("failed to create directory {}", crate_meta_dir.display()))?;
+  let () = check_crate_name(&crate_name)?;
+  let () = check_crate_version(&crate_vers)?;
 
-  let crate_length = parse_u32(&mut body)
-    .context("failed to read crate length")?
-    .try_into()
-    .unwrap();
-
-  // TODO: We may want to sanitize `metadata.vers` somewhat.
-  let data = read_crate(&mut body, crate_length).context("failed to read crate data")?;
-  let crate_meta_relative_path = crate_meta_relative_dir.join(&crate_name);
+  let crate_meta_relative_path = crate_index_path(&crate_name);
   let crate_meta_path = index.root().join(&crate_meta_relative_path);
 
-  let mut file = OpenOptions::new()
-    .write(true)
-    .create(true)
-    .append(true)
-    .open(&crate_meta_path)
-    .with_context(|| {
-      format!(
-        "failed to create crate index file {}",
-        crate_meta_path.display()
+  let entries = read_entries(&crate_meta_path)?;
+  if let Some(entry) = entries.first() {
+    ensure!(
+      entry.name == crate_name,
+      "crate name {} conflicts with existing crate {}",
+      crate_name,
+      entry.name
+    );
+  } else {
+    let normalized = normalize_crate_name(&crate_name);
+    let existing = crate_names(index.root())?
+      .into_iter()
+      .find(|name| normalize_crate_name(name) == normalized);
+    if let Some(existing) = existing {
+      // Report the name as originally published, not as normalized for
+      // the index file.
+      let entries = read_entries(&index.root().join(crate_index_path(&existing)))?;
+      let existing = entries
+        .into_iter()
+        .next()
+        .map(|entry| entry.name)
+        .unwrap_or(existing);
+      bail!(
+        "crate name {} conflicts with existing crate {}",
+        crate_name,
+        existing
       )
-    })?;
+    }
+  }
+  // Versions only differing in build metadata are considered the same,
+  // just as cargo and crates.io do.
+  let version = Version::parse(&crate_vers)?;
+  if let Some(existing) = entries.iter().find(|entry| {
+    Version::parse(&entry.vers)
+      .map_or(entry.vers == crate_vers, |vers| is_same_version(&vers, &version))
+  }) {
+    bail!(
+      "crate {} in version {} has already been published",
+      crate_name,
+      existing.vers
+    )
+  }
 
-  let entry = Entry::from((metadata, data.deref()));
-  to_writer(&mut file, &entry).context("failed to write crate index meta data")?;
-  writeln!(file).context("failed to append new line to crate index meta data file")?;
+  let () = check_owner(index.root(), &crate_name, user)?;
+  let () = check_dependencies(index.root(), &metadata.deps, policy)?;
+  let owners = read_owners(index.root(), &crate_name)?;
+
+  let crate_length = parse_u32(&mut body).context("failed to read crate length")?;
+  let () = check_policy(&metadata, crate_length.into(), policy)?;
+
+  let data = read_crate(&mut body, crate_length.try_into().unwrap())
+    .context("failed to read crate data")?;
+  let () = check_crate_tarball(&data, &crate_name, &crate_vers, policy.max_unpacked_size)
+    .context("invalid crate file")?;
 
   let crate_relative_path = PathBuf::from(crate_file_name(&crate_name, &crate_vers));
   let crate_path = index.root().join(&crate_relative_path);
-  let mut file = OpenOptions::new()
-    .write(true)
-    .create(true)
-    .truncate(true)
-    .open(&crate_path)
-    .with_context(|| format!("failed to create crate file {}", crate_path.display()))?;
-
-  file
-    .write(&data)
-    .with_context(|| format!("failed to write to crate file {}", crate_path.display()))?;
-
-  index.add(&crate_meta_relative_path).with_context(|| {
-    format!(
-      "failed to add {} to git repository (full path: {})",
-      crate_meta_relative_path.display(),
-      crate_meta_path.display(),
-    )
-  })?;
-  index
-    .add(&crate_relative_path)
-    .with_context(|| format!(
-      "failed to add {} to git repository (full path: {})",
-      crate_relative_path.display(),
-      crate_path.display(),
-    ))?;
+  // Never overwrite an existing crate file: consumers may have locked
+  // its checksum already.
+  ensure!(
+    !crate_path.exists(),
+    "crate file {} already exists",
+    crate_path.display()
+  );
+
+  let meta = CrateMeta::from(&metadata);
+  let meta_relative_path = PathBuf::from(crate_meta_file_name(&crate_name, &crate_vers));
+  let meta = to_vec_pretty(&meta).context("failed to serialize crate meta data")?;
+
+  let entry = Entry::from((metadata, data.deref()));
+  let mut index_content = match read(&crate_meta_path) {
+    Ok(content) => content,
+    Err(err) if err.kind() == ErrorKind::NotFound => Vec::new(),
+    Err(err) => {
+      return Err(err).with_context(|| {
+        format!(
+          "failed to read crate index file {}",
+          crate_meta_path.display()
+        )
+      })
+    },
+  };
+  let () = to_writer(&mut index_content, &entry).context("failed to serialize crate index entry")?;
+  let () = index_content.push(b'\n');
+
+  let mut files = vec![
+    (crate_meta_relative_path.as_path(), index_content.as_slice()),
+    (crate_relative_path.as_path(), data.deref()),
+  ];
+  // The meta data contain the full README and are only needed by the
+  // registry itself, so keep them out of the git repository.
+  let untracked = [(meta_relative_path.as_path(), meta.as_slice())];
+
+  // Crates without owners are claimed by whoever publishes them.
+  let owners_relative_path = owners_file_path(&crate_name);
+  let owners_content;
+  if let (true, Some(user)) = (owners.is_empty(), user) {
+    owners_content = format_owners(&[user.to_string()]);
+    files.push((owners_relative_path.as_path(), owners_content.as_bytes()));
+  }
+
   index
-    .commit(&format!("Add {} in version {}", crate_name, crate_vers))
+    .update(
+      &files,
+      &untracked,
+      &format!("Add {} in version {}", crate_name, crate_vers),
+    )
     .context("failed to commit changes to index")?;
 
   if !body.is_empty() {
@@ -327,7 +696,60 @@ pub fn publish_crate(mut body: Bytes, index: &mut Index) -> Result<()> {
 mod tests {
   use super::*;
 
-  use std::path::Path;
+  use std::fs::create_dir;
+  use std::fs::read_to_string;
+  use std::fs::remove_dir;
+  use std::fs::write;
+
+  use git2::Repository;
+
+  use serde_json::to_string;
+
+  use tempfile::tempdir;
+  use tempfile::TempDir;
+
+  use crate::index::IndexOptions;
+  use crate::tarball::tests::valid_crate;
+  use crate::yank::yank_crate;
+
+
+  /// Craft the body of a publish request for the crate described by the
+  /// given JSON metadata.
+  fn publish_body(metadata: &str, data: &[u8]) -> Bytes {
+    let mut body = Vec::new();
+    body.extend_from_slice(&u32::try_from(metadata.len()).unwrap().to_ne_bytes());
+    body.extend_from_slice(metadata.as_bytes());
+    body.extend_from_slice(&u32::try_from(data.len()).unwrap().to_ne_bytes());
+    body.extend_from_slice(data);
+    Bytes::from(body)
+  }
+
+  /// Craft minimal JSON metadata for a crate `name` in version `vers`.
+  fn metadata(name: &str, vers: &str) -> String {
+    format!(
+      r#"{{"name":"{name}","vers":"{vers}","deps":[],"features":{{}},"authors":[],"keywords":[],"categories":[],"badges":{{}}}}"#
+    )
+  }
+
+
+  /// Publish a crate using the default policy.
+  fn publish(body: Bytes, index: &mut Index, user: Option<&str>) -> Result<()> {
+    publish_crate(body, index, user, &PublishPolicy::default())
+  }
+
+  /// Craft the body of a publish request for a minimal but valid crate
+  /// `name` in version `vers`.
+  fn crate_body(name: &str, vers: &str) -> Bytes {
+    publish_body(&metadata(name, vers), &valid_crate(name, vers))
+  }
+
+  /// Create an empty `Index` in a new temporary directory.
+  fn index() -> (TempDir, Index) {
+    let root = tempdir().unwrap();
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let index = Index::new(root.path(), &addr, None, &IndexOptions::default()).unwrap();
+    (root, index)
+  }
 
 
   #[test]
@@ -356,6 +778,317 @@ mod tests {
     assert_eq!(body.len(), 1);
   }
 
+  /// Check that publishing an already published crate version is
+  /// rejected and leaves the registry untouched.
+  #[test]
+  fn republish_rejection() {
+    let (root, mut index) = index();
+
+    let body = crate_body("foobar", "0.1.0");
+    let () = publish(body, &mut index, None).unwrap();
+
+    let body = crate_body("foobar", "0.1.0");
+    let err = publish(body, &mut index, None).unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "crate foobar in version 0.1.0 has already been published"
+    );
+
+    let meta = read_to_string(root.path().join("fo/ob/foobar")).unwrap();
+    assert_eq!(meta.lines().count(), 1);
+    let data = read(root.path().join("foobar-0.1.0.crate")).unwrap();
+    assert_eq!(data, valid_crate("foobar", "0.1.0"));
+
+    let body = crate_body("foobar", "0.1.1");
+    let () = publish(body, &mut index, None).unwrap();
+    let meta = read_to_string(root.path().join("fo/ob/foobar")).unwrap();
+    assert_eq!(meta.lines().count(), 2);
+
+    // Build metadata does not make for a different version.
+    let body = crate_body("foobar", "1.0.0+a");
+    let () = publish(body, &mut index, None).unwrap();
+    let body = crate_body("foobar", "1.0.0+b");
+    let err = publish(body, &mut index, None).unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "crate foobar in version 1.0.0+a has already been published"
+    );
+    assert!(!root.path().join("foobar-1.0.0+b.crate").exists());
+  }
+
+  /// Check that a failed publish leaves the registry untouched.
+  #[test]
+  fn publish_rollback() {
+    let (root, mut index) = index();
+
+    let body = crate_body("foobar", "0.1.0");
+    let () = publish(body, &mut index, None).unwrap();
+    let before = read(root.path().join("fo/ob/foobar")).unwrap();
+
+    // Block the meta data file from being written, which happens after
+    // the index file and crate file have been put in place.
+    let () = create_dir(root.path().join("foobar-0.1.1.json")).unwrap();
+
+    let body = crate_body("foobar", "0.1.1");
+    let _err = publish(body, &mut index, None).unwrap_err();
+
+    assert_eq!(read(root.path().join("fo/ob/foobar")).unwrap(), before);
+    assert!(!root.path().join("foobar-0.1.1.crate").exists());
+
+    let () = remove_dir(root.path().join("foobar-0.1.1.json")).unwrap();
+    let body = crate_body("foobar", "0.1.1");
+    let () = publish(body, &mut index, None).unwrap();
+    let meta = read_to_string(root.path().join("fo/ob/foobar")).unwrap();
+    assert_eq!(meta.lines().count(), 2);
+  }
+
+  /// Check that crate names colliding after normalization are rejected.
+  #[test]
+  fn name_collision_rejection() {
+    let (root, mut index) = index();
+
+    let body = crate_body("Foo_Bar", "0.1.0");
+    let () = publish(body, &mut index, None).unwrap();
+    assert!(root.path().join("fo/o_/foo_bar").is_file());
+    assert!(root.path().join("foo_bar-0.1.0.crate").is_file());
+
+    for name in ["foo_bar", "foo-bar", "FOO-BAR"] {
+      let body = crate_body(name, "0.2.0");
+      let err = publish(body, &mut index, None).unwrap_err();
+      assert_eq!(
+        err.to_string(),
+        format!("crate name {name} conflicts with existing crate Foo_Bar")
+      );
+    }
+
+    let body = crate_body("Foo_Bar", "0.2.0");
+    let () = publish(body, &mut index, None).unwrap();
+  }
+
+  /// Check that only owners of a crate may publish new versions of it.
+  #[test]
+  fn owner_enforcement() {
+    let (root, mut index) = index();
+
+    let body = crate_body("foobar", "0.1.0");
+    let () = publish(body, &mut index, Some("alice")).unwrap();
+    let owners = read_owners(root.path(), "foobar").unwrap();
+    assert_eq!(owners, vec!["alice".to_string()]);
+
+    let body = crate_body("foobar", "0.1.1");
+    let err = publish(body, &mut index, Some("bob")).unwrap_err();
+    assert_eq!(err.to_string(), "user bob is not an owner of crate foobar");
+
+    let body = crate_body("foobar", "0.1.1");
+    let () = publish(body, &mut index, Some("alice")).unwrap();
+  }
+
+  /// Check that the meta data of a published crate are persisted.
+  #[test]
+  fn crate_meta_persistence() {
+    let (root, mut index) = index();
+
+    let meta = r#"{"name":"foobar","vers":"0.1.0","deps":[],"features":{},"authors":["me"],"description":"A crate.","keywords":["foo"],"categories":[],"license":"MIT","badges":{}}"#;
+    let body = publish_body(meta, &valid_crate("foobar", "0.1.0"));
+    let () = publish(body, &mut index, None).unwrap();
+
+    let meta = read_crate_meta(root.path(), "foobar", "0.1.0")
+      .unwrap()
+      .unwrap();
+    assert_eq!(meta.name, "foobar");
+    assert_eq!(meta.authors, vec!["me".to_string()]);
+    assert_eq!(meta.description.as_deref(), Some("A crate."));
+    assert_eq!(meta.keywords, vec!["foo".to_string()]);
+    assert_eq!(meta.license.as_deref(), Some("MIT"));
+    assert_eq!(meta.homepage, None);
+
+    let meta = read_crate_meta(root.path(), "foobar", "0.2.0").unwrap();
+    assert!(meta.is_none());
+
+    // The meta data should not be part of the git repository.
+    let repository = Repository::open(root.path()).unwrap();
+    let git_index = repository.index().unwrap();
+    assert!(git_index.get_path(Path::new("fo/ob/foobar"), 0).is_some());
+    assert!(git_index
+      .get_path(Path::new(&crate_meta_file_name("foobar", "0.1.0")), 0)
+      .is_none());
+  }
+
+  #[test]
+  fn crate_name_validation() {
+    let () = check_crate_name("foo").unwrap();
+    let () = check_crate_name("Foo_bar-2").unwrap();
+    let () = check_crate_name(&"a".repeat(64)).unwrap();
+
+    assert!(check_crate_name("").is_err());
+    assert!(check_crate_name("föö").is_err());
+    assert!(check_crate_name(&"a".repeat(65)).is_err());
+    assert!(check_crate_name("1foo").is_err());
+    assert!(check_crate_name("_foo").is_err());
+    assert!(check_crate_name("foo.bar").is_err());
+    assert!(check_crate_name("foo/../bar").is_err());
+    assert!(check_crate_name("nul").is_err());
+    assert!(check_crate_name("CON").is_err());
+    assert!(check_crate_name("lpt1").is_err());
+  }
+
+  #[test]
+  fn crate_version_validation() {
+    let () = check_crate_version("0.1.0").unwrap();
+    let () = check_crate_version("1.0.0-alpha.1+build.5").unwrap();
+
+    assert!(check_crate_version("").is_err());
+    assert!(check_crate_version("1.0").is_err());
+    assert!(check_crate_version("../../x").is_err());
+    assert!(check_crate_version("1.0.0/../x").is_err());
+  }
+
+  /// Check that yanking tolerates empty lines in a crate's index file.
+  #[test]
+  fn yank_with_empty_lines() {
+    let (root, mut index) = index();
+    let () = publish(crate_body("foobar", "0.1.0"), &mut index, None).unwrap();
+
+    let path = root.path().join("fo/ob/foobar");
+    let content = read_to_string(&path).unwrap();
+    let () = write(&path, format!("\n{content}\n")).unwrap();
+
+    let () = yank_crate(&mut index, None, "foobar", "0.1.0", true).unwrap();
+    let entries = read_entries(&path).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert!(entries[0].yanked);
+  }
+
+  /// Check that dependencies are validated if so desired.
+  #[test]
+  fn dependency_check() {
+    let (_root, mut index) = index();
+    let policy = PublishPolicy {
+      check_dependencies: true,
+      allowed_registries: vec!["https://github.com/rust-lang/crates.io-index".to_string()],
+      ..Default::default()
+    };
+
+    let () = publish(crate_body("dep", "1.0.0"), &mut index, None).unwrap();
+    let () = publish(crate_body("yanked", "1.0.0"), &mut index, None).unwrap();
+    let () = yank_crate(&mut index, None, "yanked", "1.0.0", true).unwrap();
+
+    let deps = |vers: &str, name: &str, req: &str, kind: &str, registry: Option<&str>| {
+      let registry = registry
+        .map(|registry| format!(r#""{registry}""#))
+        .unwrap_or_else(|| "null".to_string());
+      let meta = format!(
+        r#"{{"name":"foobar","vers":"{vers}","deps":[{{"name":"{name}","version_req":"{req}","features":[],"optional":false,"default_features":true,"target":null,"kind":"{kind}","registry":{registry}}}],"features":{{}},"authors":[],"keywords":[],"categories":[],"badges":{{}}}}"#
+      );
+      publish_body(&meta, &valid_crate("foobar", vers))
+    };
+
+    let body = deps("0.1.0", "dep", "^2", "normal", None);
+    let err = publish_crate(body, &mut index, None, &policy).unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "dependency dep (^2) is not satisfied by any non-yanked version in this registry"
+    );
+
+    let body = deps("0.1.0", "yanked", "^1", "build", None);
+    assert!(publish_crate(body, &mut index, None, &policy).is_err());
+
+    let body = deps("0.1.0", "unknown", "^1", "normal", None);
+    assert!(publish_crate(body, &mut index, None, &policy).is_err());
+
+    let body = deps("0.1.0", "rand", "^1", "normal", Some("https://example.com/index"));
+    let err = publish_crate(body, &mut index, None, &policy).unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "dependency rand is from registry https://example.com/index, which is not allowed"
+    );
+
+    let body = deps("0.1.0", "dep", "^1", "normal", None);
+    let () = publish_crate(body, &mut index, None, &policy).unwrap();
+
+    let registry = "https://github.com/rust-lang/crates.io-index";
+    let body = deps("0.1.1", "rand", "^1", "normal", Some(registry));
+    let () = publish_crate(body, &mut index, None, &policy).unwrap();
+
+    // Development dependencies are not checked.
+    let body = deps("0.1.2", "unknown", "^1", "dev", None);
+    let () = publish_crate(body, &mut index, None, &policy).unwrap();
+
+    // Without the check enabled, anything goes.
+    let body = deps("0.1.3", "unknown", "^1", "normal", None);
+    let () = publish(body, &mut index, None).unwrap();
+  }
+
+  /// Check that all publish policy violations are reported.
+  #[test]
+  fn policy_enforcement() {
+    let (root, mut index) = index();
+    let policy = PublishPolicy {
+      max_crate_size: 64,
+      required_fields: vec![ManifestField::Description, ManifestField::Repository],
+      allowed_licenses: vec!["MIT".to_string(), "Apache-2.0".to_string()],
+      ..Default::default()
+    };
+
+    let body = crate_body("foobar", "0.1.0");
+    let err = publish_crate(body, &mut index, None, &policy).unwrap_err();
+    let PolicyViolations(violations) = err.downcast_ref::<PolicyViolations>().unwrap();
+    assert_eq!(violations.len(), 4, "{violations:?}");
+    assert!(violations[0].starts_with("crate file size of"));
+    assert_eq!(violations[1], "manifest field `description` is required");
+    assert_eq!(violations[2], "manifest field `repository` is required");
+    assert_eq!(
+      violations[3],
+      "crate has to specify a license; allowed licenses are: MIT, Apache-2.0"
+    );
+    assert!(!root.path().join("fo/ob/foobar").exists());
+
+    let policy = PublishPolicy {
+      max_crate_size: 1024 * 1024,
+      ..policy
+    };
+    let meta = r#"{"name":"foobar","vers":"0.1.0","deps":[],"features":{},"authors":[],"description":"A crate.","keywords":[],"categories":[],"license":"GPL-3.0","repository":"https://example.com","badges":{}}"#;
+    let body = publish_body(meta, &valid_crate("foobar", "0.1.0"));
+    let err = publish_crate(body, &mut index, None, &policy).unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "license `GPL-3.0` is not allowed; allowed licenses are: MIT, Apache-2.0"
+    );
+
+    let meta = meta.replace("GPL-3.0", "MIT OR Apache-2.0");
+    let body = publish_body(&meta, &valid_crate("foobar", "0.1.0"));
+    let () = publish_crate(body, &mut index, None, &policy).unwrap();
+  }
+
+  /// Check that a corrupt crate file is rejected.
+  #[test]
+  fn corrupt_crate_rejection() {
+    let (root, mut index) = index();
+
+    let body = publish_body(&metadata("foobar", "0.1.0"), b"garbage");
+    let err = publish(body, &mut index, None).unwrap_err();
+    assert_eq!(err.to_string(), "invalid crate file");
+    assert!(!root.path().join("foobar-0.1.0.crate").exists());
+
+    let body = publish_body(&metadata("foobar", "0.1.0"), &valid_crate("foobar", "0.2.0"));
+    let _err = publish(body, &mut index, None).unwrap_err();
+    assert!(!root.path().join("fo/ob/foobar").exists());
+  }
+
+  /// Check that a crate with a malicious version is rejected.
+  #[test]
+  fn invalid_version_rejection() {
+    let (root, mut index) = index();
+
+    let body = crate_body("foobar", "../../x");
+    let err = publish(body, &mut index, None).unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "crate version ../../x is not a valid semantic version"
+    );
+    assert!(!root.path().join("fo/ob/foobar").exists());
+  }
+
   #[test]
   fn crate_path_construction() {
     assert_eq!(&crate_path("r"), Path::new("1"));
@@ -363,6 +1096,52 @@ mod tests {
     assert_eq!(&crate_path("abc"), Path::new("3/a"));
     assert_eq!(&crate_path("abcd"), Path::new("ab/cd"));
     assert_eq!(&crate_path("ydasdayusiy"), Path::new("yd/as"));
+    assert_eq!(&crate_path("MyCrate"), Path::new("my/cr"));
+    assert_eq!(&crate_index_path("MyCrate"), Path::new("my/cr/mycrate"));
+  }
+
+  #[test]
+  fn crate_index_path_detection() {
+    assert!(is_crate_index_path("1/r"));
+    assert!(is_crate_index_path("2/xy"));
+    assert!(is_crate_index_path("3/a/abc"));
+    assert!(is_crate_index_path("ab/cd/abcd"));
+    assert!(is_crate_index_path("my/-l/my-lib"));
+
+    assert!(!is_crate_index_path(""));
+    assert!(!is_crate_index_path("abcd"));
+    assert!(!is_crate_index_path("ab/cd/"));
+    assert!(!is_crate_index_path("ab/ce/abcd"));
+    assert!(!is_crate_index_path("3/a/abc.crate"));
+    assert!(!is_crate_index_path(".git/HEAD"));
+    assert!(!is_crate_index_path("index/ab/cd/abcd"));
+    assert!(!is_crate_index_path("Ab/cd/Abcd"));
+    assert!(!is_crate_index_path("ab/cd/Abcd"));
+  }
+
+  /// Check that features using new syntax end up in `features2`.
+  #[test]
+  fn features2_conversion() {
+    let meta = r#"{"name":"foobar","vers":"0.1.0","deps":[],"features":{"default":["std"],"std":[],"serde":["dep:serde"],"weak":["bar?/baz"]},"authors":[],"keywords":[],"categories":[],"badges":{},"rust_version":"1.64"}"#;
+    let data = from_slice::<MetaData>(meta.as_bytes()).unwrap();
+    let entry = Entry::from((data, b"data".as_slice()));
+
+    assert_eq!(entry.features.keys().collect::<Vec<_>>(), ["default", "std"]);
+    let features2 = entry.features2.unwrap();
+    assert_eq!(features2.keys().collect::<Vec<_>>(), ["serde", "weak"]);
+    assert_eq!(entry.v, Some(2));
+    assert_eq!(entry.rust_version.as_deref(), Some("1.64"));
+
+    let meta = metadata("foobar", "0.1.0");
+    let data = from_slice::<MetaData>(meta.as_bytes()).unwrap();
+    let entry = Entry::from((data, b"data".as_slice()));
+    assert_eq!(entry.features2, None);
+    assert_eq!(entry.v, None);
+    assert_eq!(entry.rust_version, None);
+
+    let json = to_string(&entry).unwrap();
+    assert!(!json.contains("features2"));
+    assert!(!json.contains(r#""v""#));
   }
 
   #[test]
@@ -0,0 +1,282 @@
+// Copyright (C) 2026 The cargo-http-registry Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fs::read_dir;
+use std::path::Path;
+
+use anyhow::Context as _;
+use anyhow::Result;
+
+use semver::Version;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::index::read_entries;
+use crate::index::Entry;
+use crate::publish::crate_index_path;
+use crate::publish::is_crate_index_path;
+use crate::publish::normalize_crate_name;
+use crate::publish::read_crate_meta;
+
+
+/// The number of results returned if the client did not ask for a
+/// specific amount.
+const DEFAULT_PER_PAGE: usize = 10;
+/// The maximum number of results we return. This is the same limit
+/// crates.io employs.
+const MAX_PER_PAGE: usize = 100;
+
+
+/// The query parameters of a search request.
+#[derive(Debug, Default, Deserialize)]
+pub struct SearchQuery {
+  /// The search term.
+  #[serde(default)]
+  q: String,
+  /// The maximum number of results to return.
+  per_page: Option<usize>,
+}
+
+/// A single crate matching a search.
+#[derive(Debug, Deserialize, Serialize)]
+struct SearchCrate {
+  /// The name of the crate.
+  name: String,
+  /// The highest version of the crate available.
+  max_version: String,
+  /// The description of the crate, if any.
+  description: Option<String>,
+}
+
+/// Meta data about a search.
+#[derive(Debug, Deserialize, Serialize)]
+struct SearchMeta {
+  /// The total number of crates matching the search, irrespective of
+  /// the number of results reported.
+  total: usize,
+}
+
+/// The response to a search request.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SearchResults {
+  crates: Vec<SearchCrate>,
+  meta: SearchMeta,
+}
+
+
+/// Determine the maximum version of a crate from its index entries.
+///
+/// Yanked versions are only considered if no other ones are available.
+fn max_version(entries: &[Entry]) -> Option<&Entry> {
+  let max = |yanked: bool| {
+    entries
+      .iter()
+      .filter(|entry| yanked || !entry.yanked)
+      .filter_map(|entry| Version::parse(&entry.vers).ok().map(|vers| (vers, entry)))
+      .max_by(|(vers1, _), (vers2, _)| vers1.cmp(vers2))
+      .map(|(_, entry)| entry)
+  };
+
+  max(false).or_else(|| max(true))
+}
+
+
+/// Recursively collect the names of all crates below `dir`.
+///
+/// `relative` is the path of `dir` relative to the index root, using
+/// forward slashes as separators.
+fn collect_crates(dir: &Path, relative: &str, names: &mut Vec<String>) -> Result<()> {
+  let entries =
+    read_dir(dir).with_context(|| format!("failed to read directory {}", dir.display()))?;
+
+  for entry in entries {
+    let entry =
+      entry.with_context(|| format!("failed to read directory entry in {}", dir.display()))?;
+    let file_type = entry
+      .file_type()
+      .with_context(|| format!("failed to inquire type of {}", entry.path().display()))?;
+    let name = entry.file_name();
+    let name = match name.to_str() {
+      Some(name) => name,
+      None => continue,
+    };
+    let path = if relative.is_empty() {
+      name.to_string()
+    } else {
+      format!("{}/{}", relative, name)
+    };
+
+    // Note that we skip symbolic links, which most importantly
+    // includes the recursive `index` link.
+    if file_type.is_dir() {
+      if name != ".git" {
+        let () = collect_crates(&entry.path(), &path, names)?;
+      }
+    } else if file_type.is_file() && is_crate_index_path(&path) {
+      names.push(name.to_string());
+    }
+  }
+  Ok(())
+}
+
+
+/// Retrieve the names of all crates in the index at `root`.
+///
+/// Names are reported as used for the index files, i.e., lower case.
+pub fn crate_names(root: &Path) -> Result<Vec<String>> {
+  let mut names = Vec::new();
+  let () = collect_crates(root, "", &mut names)?;
+  Ok(names)
+}
+
+
+/// Handler for the `/api/v1/crates` endpoint.
+///
+/// Search the index at `root` for crates whose name contains the query
+/// string.
+pub fn search_crates(root: &Path, query: &SearchQuery) -> Result<SearchResults> {
+  let per_page = query
+    .per_page
+    .unwrap_or(DEFAULT_PER_PAGE)
+    .min(MAX_PER_PAGE);
+  let term = normalize_crate_name(&query.q);
+
+  let mut names = crate_names(root)?
+    .into_iter()
+    .filter(|name| normalize_crate_name(name).contains(&term))
+    .collect::<Vec<_>>();
+  // Exact matches go first, everything else is sorted by name.
+  names.sort_by_key(|name| (normalize_crate_name(name) != term, name.clone()));
+
+  let total = names.len();
+  let mut crates = Vec::new();
+
+  for name in names.into_iter().take(per_page) {
+    let path = root.join(crate_index_path(&name));
+    let entries = read_entries(&path)?;
+    if let Some(entry) = max_version(&entries) {
+      let description =
+        read_crate_meta(root, &entry.name, &entry.vers)?.and_then(|meta| meta.description);
+      crates.push(SearchCrate {
+        name: entry.name.clone(),
+        max_version: entry.vers.clone(),
+        description,
+      });
+    }
+  }
+
+  let results = SearchResults {
+    crates,
+    meta: SearchMeta { total },
+  };
+  Ok(results)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::fs::create_dir_all;
+  use std::fs::write;
+
+  use serde_json::to_string;
+
+  use tempfile::tempdir;
+
+  use crate::publish::crate_path;
+  use crate::publish::CrateMeta;
+
+
+  /// Add an index file for crate `name` with the given versions to the
+  /// index at `root`.
+  fn add_crate(root: &Path, name: &str, versions: &[(&str, bool)]) {
+    let dir = root.join(crate_path(name));
+    let () = create_dir_all(&dir).unwrap();
+
+    let content = versions
+      .iter()
+      .map(|(vers, yanked)| {
+        format!(
+          r#"{{"name":"{name}","vers":"{vers}","deps":[],"cksum":"","features":{{}},"yanked":{yanked},"links":null}}"#
+        )
+      })
+      .collect::<Vec<_>>()
+      .join("\n");
+    let () = write(dir.join(name), content).unwrap();
+  }
+
+
+  /// Check that we pick the correct maximum version of a crate.
+  #[test]
+  fn max_version_selection() {
+    let root = tempdir().unwrap();
+    add_crate(
+      root.path(),
+      "foobar",
+      &[("0.9.0", false), ("0.10.0", false), ("0.11.0", true)],
+    );
+    add_crate(root.path(), "yanked", &[("1.0.0", true), ("1.1.0", true)]);
+
+    let entries = read_entries(&root.path().join("fo/ob/foobar")).unwrap();
+    assert_eq!(max_version(&entries).unwrap().vers, "0.10.0");
+
+    let entries = read_entries(&root.path().join("ya/nk/yanked")).unwrap();
+    assert_eq!(max_version(&entries).unwrap().vers, "1.1.0");
+    assert!(max_version(&[]).is_none());
+  }
+
+  /// Check that searching the index works as expected.
+  #[test]
+  fn crate_search() {
+    let root = tempdir().unwrap();
+    add_crate(root.path(), "foo", &[("0.1.0", false)]);
+    add_crate(root.path(), "foo-bar", &[("0.2.0", false)]);
+    add_crate(root.path(), "barfoo", &[("0.3.0", false)]);
+    add_crate(root.path(), "baz", &[("0.4.0", false)]);
+    let () = write(root.path().join("foo-0.1.0.crate"), b"").unwrap();
+    let meta = CrateMeta {
+      name: "foo".to_string(),
+      vers: "0.1.0".to_string(),
+      description: Some("The foo crate".to_string()),
+      ..Default::default()
+    };
+    let () = write(root.path().join("foo-0.1.0.json"), to_string(&meta).unwrap()).unwrap();
+
+    let query = SearchQuery {
+      q: "Foo".to_string(),
+      per_page: None,
+    };
+    let results = search_crates(root.path(), &query).unwrap();
+    assert_eq!(results.meta.total, 3);
+    let names = results
+      .crates
+      .iter()
+      .map(|krate| krate.name.as_str())
+      .collect::<Vec<_>>();
+    assert_eq!(names, ["foo", "barfoo", "foo-bar"]);
+    assert_eq!(
+      results.crates[0].description.as_deref(),
+      Some("The foo crate")
+    );
+    assert_eq!(results.crates[2].max_version, "0.2.0");
+    assert_eq!(results.crates[2].description, None);
+
+    let query = SearchQuery {
+      q: "foo_bar".to_string(),
+      per_page: Some(1),
+    };
+    let results = search_crates(root.path(), &query).unwrap();
+    assert_eq!(results.meta.total, 1);
+    assert_eq!(results.crates[0].name, "foo-bar");
+
+    let query = SearchQuery {
+      q: String::new(),
+      per_page: Some(2),
+    };
+    let results = search_crates(root.path(), &query).unwrap();
+    assert_eq!(results.meta.total, 4);
+    assert_eq!(results.crates.len(), 2);
+  }
+}
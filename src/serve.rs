@@ -2,29 +2,77 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use std::future::Future;
+use std::io::ErrorKind;
 use std::net::SocketAddr;
 use std::path::Path;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::Mutex;
 
+use anyhow::ensure;
 use anyhow::Context as _;
 use anyhow::Error;
 use anyhow::Result;
 
 use serde::Deserialize;
 use serde::Serialize;
+use serde_json::json;
+
+use sha2::Digest as _;
+use sha2::Sha256;
+
+use futures_util::future::Either;
+
+use tokio::fs::read;
+use tokio::spawn;
+use tokio::sync::watch;
+use tokio::task::spawn_blocking;
 
 use tracing::error;
 use tracing::info;
 
+use warp::http::header::CACHE_CONTROL;
+use warp::http::header::CONTENT_TYPE;
+use warp::http::header::ETAG;
+use warp::http::header::WWW_AUTHENTICATE;
 use warp::http::StatusCode;
 use warp::http::Uri;
-use warp::Filter as _;
+use warp::hyper::body::Bytes;
+use warp::path::Tail;
+use warp::reject::PayloadTooLarge;
+use warp::reject::Reject;
+use warp::reply::Response;
+use warp::Filter;
+use warp::Rejection;
 use warp::Reply as _;
 
+use crate::auth::Auth;
+use crate::index::GitAuthor;
 use crate::index::Index;
+use crate::index::IndexOptions;
+use crate::owners::change_owners;
+use crate::owners::list_owners;
+use crate::owners::OwnersChange;
+use crate::policy::PolicyViolations;
+use crate::policy::PublishPolicy;
 use crate::publish::crate_file_name;
+use crate::publish::is_crate_index_path;
 use crate::publish::publish_crate;
+use crate::search::search_crates;
+use crate::search::SearchQuery;
+use crate::tls::Acceptor;
+use crate::tls::TlsConfig;
+use crate::upload_pack::advertise_refs;
+use crate::upload_pack::upload_pack;
+use crate::upload_pack::ADVERTISEMENT_CONTENT_TYPE;
+use crate::upload_pack::MAX_REQUEST_SIZE;
+use crate::upload_pack::RESULT_CONTENT_TYPE;
+use crate::yank::yank_crate;
+
+
+/// The maximum size of the JSON meta data accompanying a crate upload,
+/// in bytes. These meta data include the crate's README.
+const MAX_METADATA_SIZE: u64 = 5 * 1024 * 1024;
 
 
 /// A single error that the registry returns.
@@ -41,6 +89,19 @@ struct RegistryErrors {
 
 impl From<Error> for RegistryErrors {
   fn from(error: Error) -> Self {
+    // Policy violations are reported individually, so that users get to
+    // see everything they need to fix at once.
+    if let Some(PolicyViolations(violations)) = error.downcast_ref::<PolicyViolations>() {
+      return Self {
+        errors: violations
+          .iter()
+          .map(|violation| RegistryError {
+            detail: violation.clone(),
+          })
+          .collect(),
+      }
+    }
+
     Self {
       errors: error
         .chain()
@@ -52,6 +113,210 @@ impl From<Error> for RegistryErrors {
 }
 
 
+/// Options influencing how a registry is served.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+  /// The path to a file containing hashed tokens granting access to the
+  /// registry. If set, publishing and other mutating operations require
+  /// one of these tokens to be provided.
+  pub token_file: Option<PathBuf>,
+  /// Whether to require authentication for read accesses as well. This
+  /// setting is advertised to clients as `auth-required` and only
+  /// supported by cargo for the sparse protocol.
+  pub auth_required: bool,
+  /// The policy to apply when publishing crates.
+  pub publish_policy: PublishPolicy,
+  /// The identity to author commits to the index as.
+  pub git_author: GitAuthor,
+  /// The URL under which the registry is reachable by clients, e.g.,
+  /// when running behind a reverse proxy. It may contain a path prefix,
+  /// below which all routes are then served. If not set, the registry
+  /// is assumed to be reachable at the bound address.
+  pub public_url: Option<String>,
+  /// The certificate and key to serve HTTPS with. If not set, plain HTTP
+  /// is used. The certificate is reloaded on `SIGHUP`.
+  pub tls: Option<TlsConfig>,
+  /// A trigger for shutting down the server.
+  pub shutdown: Shutdown,
+  /// Whether to serve an existing registry read-only. In this mode,
+  /// publishing and other modifications are rejected and the registry
+  /// is served as is, e.g., without updating `config.json` to the
+  /// address served on. Unless set, the registry root is locked
+  /// exclusively, so that only a single server at a time can manage it.
+  /// Read-only replicas do not take this lock.
+  pub read_only: bool,
+}
+
+
+/// A trigger for gracefully shutting down a registry server.
+///
+/// Once triggered, the server stops accepting new connections and the
+/// future returned by [`serve`] completes after all in-flight requests,
+/// including publishes, have been handled.
+#[derive(Clone, Debug)]
+pub struct Shutdown(Arc<watch::Sender<bool>>);
+
+impl Shutdown {
+  /// Trigger the shutdown.
+  pub fn trigger(&self) {
+    let _prev = self.0.send_replace(true);
+  }
+
+  /// Wait for the shutdown to be triggered.
+  async fn triggered(self) {
+    let mut receiver = self.0.subscribe();
+    // The sender is kept alive by `self`, so waiting cannot fail.
+    let _result = receiver.wait_for(|triggered| *triggered).await;
+  }
+}
+
+impl Default for Shutdown {
+  fn default() -> Self {
+    let (sender, _receiver) = watch::channel(false);
+    Self(Arc::new(sender))
+  }
+}
+
+
+/// The query string of a git smart HTTP reference advertisement
+/// request.
+#[derive(Debug, Deserialize)]
+struct ServiceQuery {
+  service: String,
+}
+
+
+/// A rejection indicating that a request lacks proper authentication.
+#[derive(Debug)]
+struct Unauthorized(String);
+
+impl Reject for Unauthorized {}
+
+
+/// A rejection indicating that a request body exceeds the given size
+/// limit, in bytes.
+#[derive(Debug)]
+struct TooLarge(u64);
+
+impl Reject for TooLarge {}
+
+
+/// Create a filter limiting the size of a request body to `limit`
+/// bytes, rejecting requests exceeding it with a [`TooLarge`]
+/// rejection.
+fn body_limit(limit: u64) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+  warp::body::content_length_limit(limit).or_else(move |rejection: Rejection| async move {
+    if rejection.find::<PayloadTooLarge>().is_some() {
+      Err(warp::reject::custom(TooLarge(limit)))
+    } else {
+      Err(rejection)
+    }
+  })
+}
+
+
+/// Create a filter authenticating read accesses, if so required.
+fn authenticate_read(
+  auth: Arc<Auth>,
+  required: bool,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+  warp::header::optional::<String>("authorization")
+    .and_then(move |token: Option<String>| {
+      let auth = auth.clone();
+      async move {
+        if required {
+          let _user = auth
+            .authenticate(token.as_deref())
+            .map_err(|err| warp::reject::custom(Unauthorized(err.to_string())))?;
+        }
+        Ok::<_, Rejection>(())
+      }
+    })
+    .untuple_one()
+}
+
+/// Create a filter authenticating write accesses, extracting the name
+/// of the authenticated user, if any.
+///
+/// Contrary to authenticating inside a handler, this filter can be
+/// used to reject requests before their body is read.
+fn authenticate_write(
+  auth: Arc<Auth>,
+) -> impl Filter<Extract = (Option<String>,), Error = Rejection> + Clone {
+  warp::header::optional::<String>("authorization").and_then(move |token: Option<String>| {
+    let auth = auth.clone();
+    async move {
+      auth
+        .authenticate(token.as_deref())
+        .map(|user| user.map(str::to_owned))
+        .map_err(|err| warp::reject::custom(Unauthorized(err.to_string())))
+    }
+  })
+}
+
+
+/// Convert rejections due to lacking authentication or excessive
+/// request body sizes into a response.
+async fn handle_rejection(rejection: Rejection) -> Result<Response, Rejection> {
+  if let Some(Unauthorized(detail)) = rejection.find::<Unauthorized>() {
+    error!("request status: unauthorized: {}", detail);
+
+    let errors = RegistryErrors {
+      errors: vec![RegistryError {
+        detail: detail.clone(),
+      }],
+    };
+    // Cargo relies on a 401 status to know that it should (re)try with
+    // a token, so we deviate from our usual "always OK" scheme here.
+    let reply = warp::reply::with_status(warp::reply::json(&errors), StatusCode::UNAUTHORIZED);
+    let reply = warp::reply::with_header(reply, WWW_AUTHENTICATE, "Cargo");
+    Ok(reply.into_response())
+  } else if let Some(TooLarge(limit)) = rejection.find::<TooLarge>() {
+    let detail = format!("request body exceeds the maximum size of {} bytes", limit);
+    error!("request status: payload too large: {}", detail);
+
+    let errors = RegistryErrors {
+      errors: vec![RegistryError { detail }],
+    };
+    let reply = warp::reply::with_status(warp::reply::json(&errors), StatusCode::PAYLOAD_TOO_LARGE);
+    Ok(reply.into_response())
+  } else {
+    Err(rejection)
+  }
+}
+
+
+/// Parse a public URL, returning the segments of its path.
+fn parse_public_url(url: &str) -> Result<Vec<String>> {
+  let uri = url
+    .parse::<Uri>()
+    .with_context(|| format!("public URL {} is invalid", url))?;
+  ensure!(
+    matches!(uri.scheme_str(), Some("http") | Some("https")),
+    "public URL {} does not use the http or https scheme",
+    url
+  );
+  ensure!(
+    uri.authority().is_some(),
+    "public URL {} lacks a host",
+    url
+  );
+  ensure!(
+    uri.query().is_none(),
+    "public URL {} must not contain a query",
+    url
+  );
+
+  let segments = uri
+    .path()
+    .split('/')
+    .filter(|segment| !segment.is_empty())
+    .map(str::to_string)
+    .collect();
+  Ok(segments)
+}
+
+
 /// Convert a result back into a response.
 async fn response<T>(result: Result<T>) -> Result<impl warp::Reply, warp::Rejection>
 where
@@ -76,8 +341,135 @@ where
 }
 
 
+/// Check that the registry may be modified.
+fn ensure_writable(read_only: bool) -> Result<()> {
+  ensure!(
+    !read_only,
+    "the registry is served read-only and does not accept modifications"
+  );
+  Ok(())
+}
+
+
+/// Run `f` on a thread dedicated to blocking work.
+///
+/// Request handlers performing disk I/O or otherwise taking a while use
+/// this function, so that the runtime continues serving other requests,
+/// such as downloads and sparse index fetches, in the meantime.
+async fn blocking<F, T>(f: F) -> Result<T>
+where
+  F: FnOnce() -> Result<T> + Send + 'static,
+  T: Send + 'static,
+{
+  spawn_blocking(f)
+    .await
+    .context("blocking operation failed to complete")?
+}
+
+/// Run `f` with exclusive access to the index, on a thread dedicated to
+/// blocking work.
+async fn with_index<F, T>(shared: Arc<Mutex<Option<Index>>>, f: F) -> Result<T>
+where
+  F: FnOnce(&mut Index) -> Result<T> + Send + 'static,
+  T: Send + 'static,
+{
+  blocking(move || {
+    let mut index = shared.lock().unwrap();
+    let index = index.as_mut().unwrap();
+    f(index)
+  })
+  .await
+}
+
+
+/// Convert the result of a git smart HTTP request into a response.
+fn git_response(result: Result<Vec<u8>>, content_type: &'static str) -> Response {
+  match result {
+    Ok(data) => {
+      let reply = warp::reply::with_header(data, CONTENT_TYPE, content_type);
+      warp::reply::with_header(reply, CACHE_CONTROL, "no-cache").into_response()
+    },
+    Err(err) => {
+      error!("git request failed: {:#}", err);
+      let reply = format!("{:#}", err);
+      warp::reply::with_status(reply, StatusCode::INTERNAL_SERVER_ERROR).into_response()
+    },
+  }
+}
+
+
+/// Serve a file from the index using cargo's sparse protocol.
+///
+/// A checksum of the file's contents is used as entity tag, which
+/// allows clients to cache data reliably. Modification times, on the
+/// other hand, have too coarse a granularity for that purpose.
+async fn serve_index_file(root: PathBuf, tail: Tail, etag: Option<String>) -> Response {
+  let path = tail.as_str();
+  if path != "config.json" && !is_crate_index_path(path) {
+    return StatusCode::NOT_FOUND.into_response()
+  }
+
+  let path = root.join(path);
+  let data = match read(&path).await {
+    Ok(data) => data,
+    // Cargo expects a plain 404 for crates not present in the index.
+    Err(err) if err.kind() == ErrorKind::NotFound => return StatusCode::NOT_FOUND.into_response(),
+    Err(err) => {
+      error!("failed to read {}: {}", path.display(), err);
+      return StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    },
+  };
+
+  let checksum = format!("\"{:x}\"", Sha256::digest(&data));
+  if etag.as_deref() == Some(&checksum) {
+    warp::reply::with_header(StatusCode::NOT_MODIFIED, ETAG, checksum).into_response()
+  } else {
+    warp::reply::with_header(data, ETAG, checksum).into_response()
+  }
+}
+
+
 /// Serve a registry at the given path on the given socket address.
-pub fn serve(root: &Path, addr: SocketAddr) -> Result<(impl Future<Output = ()>, SocketAddr)> {
+pub fn serve(
+  root: &Path,
+  addr: SocketAddr,
+  options: Options,
+) -> Result<(impl Future<Output = ()>, SocketAddr)> {
+  let auth = match &options.token_file {
+    Some(path) => Auth::load(path)?,
+    None => Auth::default(),
+  };
+  ensure!(
+    !options.auth_required || options.token_file.is_some(),
+    "requiring authentication for reads needs a token file"
+  );
+
+  let prefix = match &options.public_url {
+    Some(url) => parse_public_url(url)?,
+    None => Vec::new(),
+  };
+  // The absolute path below which all our routes reside.
+  let base_path = prefix
+    .iter()
+    .map(|segment| format!("/{}", segment))
+    .collect::<String>();
+  let prefix = prefix
+    .into_iter()
+    .fold(warp::any().boxed(), |filter, segment| {
+      filter.and(warp::path(segment)).boxed()
+    });
+
+  let publish_policy = options.publish_policy;
+  let read_only = options.read_only;
+  let max_body_size = publish_policy
+    .max_crate_size
+    .saturating_add(MAX_METADATA_SIZE);
+  let auth = Arc::new(auth);
+  let publish_auth = auth.clone();
+  let yank_auth = auth.clone();
+  let owners_auth = auth.clone();
+  let read_auth = authenticate_read(auth, options.auth_required);
+
   // Unfortunately because of how we have to define our routes in order
   // to create our server and we need a server in order to bind it while
   // also needing to bind in order to have the necessary address for the
@@ -85,14 +477,63 @@ pub fn serve(root: &Path, addr: SocketAddr) -> Result<(impl Future<Output = ()>,
   // of an `Option`. *sadpanda*
   let shared = Arc::new(Mutex::new(Option::<Index>::None));
   let copy = shared.clone();
+  let yank_copy = shared.clone();
+  let owners_copy = shared.clone();
 
+  // Serve the index via git's smart HTTP protocol. Clients not asking
+  // for a service get to use the dumb protocol below.
+  let refs_root = root.to_owned();
+  let smart_refs = warp::get()
+    .and(warp::path!("git" / "info" / "refs"))
+    .and(read_auth.clone())
+    .and(warp::query::<ServiceQuery>())
+    .then(move |query: ServiceQuery| {
+      let root = refs_root.clone();
+      async move {
+        if query.service != "git-upload-pack" {
+          return StatusCode::FORBIDDEN.into_response()
+        }
+        let result = blocking(move || advertise_refs(&root)).await;
+        git_response(result, ADVERTISEMENT_CONTENT_TYPE)
+      }
+    });
+  let upload_root = root.to_owned();
+  let smart_upload = warp::post()
+    .and(warp::path!("git" / "git-upload-pack"))
+    .and(read_auth.clone())
+    .and(warp::header::optional::<String>("content-encoding"))
+    .and(warp::body::content_length_limit(MAX_REQUEST_SIZE))
+    .and(warp::body::bytes())
+    .then(move |encoding: Option<String>, body: Bytes| {
+      let root = upload_root.clone();
+      async move {
+        let compressed = encoding.as_deref() == Some("gzip");
+        let result = blocking(move || upload_pack(&root, &body, compressed)).await;
+        git_response(result, RESULT_CONTENT_TYPE)
+      }
+    });
   // Serve the contents of <root>/.git at /git.
-  let index = warp::path("git").and(warp::fs::dir(root.join(".git")));
+  let index = warp::path("git")
+    .and(read_auth.clone())
+    .and(warp::fs::dir(root.join(".git")));
   //.with(warp::trace::request());
+  // Serve the index at /index using cargo's sparse protocol. Only
+  // config.json and the per-crate index files are accessible this way.
+  let index_root = root.to_owned();
+  let sparse = warp::get()
+    .and(warp::path("index"))
+    .and(read_auth.clone())
+    .and(warp::path::tail())
+    .and(warp::header::optional::<String>("if-none-match"))
+    .then(move |tail: Tail, etag: Option<String>| {
+      serve_index_file(index_root.clone(), tail, etag)
+    });
   // Serve the contents of <root>/ at /crates. This allows for directly
   // downloading the .crate files, to which we redirect from the
   // download handler below.
-  let crates = warp::path("crates").and(warp::fs::dir(root.to_owned()));
+  let crates = warp::path("crates")
+    .and(read_auth.clone())
+    .and(warp::fs::dir(root.to_owned()));
   let download = warp::get()
     .and(warp::path("api"))
     .and(warp::path("v1"))
@@ -100,55 +541,174 @@ pub fn serve(root: &Path, addr: SocketAddr) -> Result<(impl Future<Output = ()>,
     .and(warp::path::param())
     .and(warp::path::param())
     .and(warp::path("download"))
+    .and(read_auth.clone())
     .map(move |name: String, version: String| {
-      let path = format!("/crates/{}", crate_file_name(&name, &version));
+      let path = format!("{}/crates/{}", base_path, crate_file_name(&name, &version));
       // TODO: Ideally we shouldn't unwrap here. That's not that easily
       //       possible, though, because then we'd need to handle errors
       //       and we can't use the response function because it will
       //       overwrite the HTTP status even on success.
       path.parse::<Uri>().map(warp::redirect).unwrap()
     });
+  let search_root = root.to_owned();
+  let search = warp::get()
+    .and(warp::path("api"))
+    .and(warp::path("v1"))
+    .and(warp::path("crates"))
+    .and(warp::path::end())
+    .and(read_auth.clone())
+    .and(warp::query::<SearchQuery>())
+    .then(move |query: SearchQuery| {
+      let root = search_root.clone();
+      blocking(move || {
+        search_crates(&root, &query).map(|results| warp::reply::json(&results))
+      })
+    })
+    .and_then(response);
   let publish = warp::put()
     .and(warp::path("api"))
     .and(warp::path("v1"))
     .and(warp::path("crates"))
     .and(warp::path("new"))
     .and(warp::path::end())
+    .and(warp::header::optional::<String>("authorization"))
+    // We cap total body size to have some upper bound. The crate file
+    // itself is checked against the exact limit later, so that users
+    // get a meaningful error. Note that the cap has to be in place
+    // before reading the body for it to limit memory use.
+    .and(body_limit(max_body_size))
     .and(warp::body::bytes())
-    // We cap total body size to 20 MiB to have some upper bound. At the
-    // time of last check, crates.io employed a limit of 10 MiB.
-    .and(warp::body::content_length_limit(20 * 1024 * 1024))
-    .map(move |body| {
-      let mut index = copy.lock().unwrap();
-      let index = index.as_mut().unwrap();
-      publish_crate(body, index).map(|()| String::new())
+    .then(move |token: Option<String>, body| {
+      let auth = publish_auth.clone();
+      let shared = copy.clone();
+      let policy = publish_policy.clone();
+      async move {
+        let () = ensure_writable(read_only)?;
+        let user = auth.authenticate(token.as_deref())?.map(str::to_owned);
+        with_index(shared, move |index| {
+          publish_crate(body, index, user.as_deref(), &policy).map(|()| String::new())
+        })
+        .await
+      }
     })
     .and_then(response);
+  let yank = warp::path("api")
+    .and(warp::path("v1"))
+    .and(warp::path("crates"))
+    .and(warp::path::param())
+    .and(warp::path::param())
+    .and(
+      warp::delete()
+        .and(warp::path("yank"))
+        .map(|| true)
+        .or(warp::put().and(warp::path("unyank")).map(|| false))
+        .unify(),
+    )
+    .and(warp::path::end())
+    .and(warp::header::optional::<String>("authorization"))
+    .then(move |name: String, version: String, yanked: bool, token: Option<String>| {
+      let auth = yank_auth.clone();
+      let shared = yank_copy.clone();
+      async move {
+        let () = ensure_writable(read_only)?;
+        let user = auth.authenticate(token.as_deref())?.map(str::to_owned);
+        with_index(shared, move |index| {
+          yank_crate(index, user.as_deref(), &name, &version, yanked)
+            .map(|()| warp::reply::json(&json!({"ok": true})))
+        })
+        .await
+      }
+    })
+    .and_then(response);
+  let owners_root = root.to_owned();
+  let owners_list = warp::get()
+    .and(warp::path("api"))
+    .and(warp::path("v1"))
+    .and(warp::path("crates"))
+    .and(warp::path::param())
+    .and(warp::path("owners"))
+    .and(warp::path::end())
+    .and(read_auth)
+    .then(move |name: String| {
+      let root = owners_root.clone();
+      blocking(move || list_owners(&root, &name).map(|owners| warp::reply::json(&owners)))
+    })
+    .and_then(response);
+  let owners_change = warp::path("api")
+    .and(warp::path("v1"))
+    .and(warp::path("crates"))
+    .and(warp::path::param())
+    .and(warp::path("owners"))
+    .and(warp::path::end())
+    .and(
+      warp::put()
+        .map(|| true)
+        .or(warp::delete().map(|| false))
+        .unify(),
+    )
+    .and(authenticate_write(owners_auth.clone()))
+    .and(body_limit(MAX_REQUEST_SIZE))
+    .and(warp::body::json())
+    .then(move |name: String, add: bool, user: Option<String>, change: OwnersChange| {
+      let auth = owners_auth.clone();
+      let shared = owners_copy.clone();
+      async move {
+        let () = ensure_writable(read_only)?;
+        with_index(shared, move |index| {
+          change_owners(index, &auth, user.as_deref(), &name, change, add)
+            .map(|result| warp::reply::json(&result))
+        })
+        .await
+      }
+    })
+    .and_then(response);
+
+  let acceptor = options.tls.clone().map(Acceptor::new).transpose()?;
 
   let mut addr = addr;
   let original_port = addr.port();
   // If the port is kernel-assigned then see if we can just use the
   // same one we used last time, to prevent needless updates of our
-  // configuration file.
-  if addr.port() == 0 {
+  // configuration file. With a public URL set, the port is irrelevant
+  // for the configuration.
+  if addr.port() == 0 && options.public_url.is_none() {
     if let Ok(port) = Index::try_read_port(root) {
       addr.set_port(port)
     }
   }
 
   let (addr, serve) = loop {
-    let routes = index
+    let routes = smart_refs
       .clone()
+      .or(smart_upload.clone())
+      .or(index.clone())
+      .or(sparse.clone())
       .or(crates.clone())
-      .or(download)
+      .or(download.clone())
+      .or(search.clone())
       .or(publish.clone())
+      .or(yank.clone())
+      .or(owners_list.clone())
+      .or(owners_change.clone());
+    let routes = prefix
+      .clone()
+      .and(routes)
+      .recover(handle_rejection)
       .with(warp::trace::request());
     // Despite the claim that this function "Returns [...] a Future that
     // can be executed on any runtime." not even the call itself can
     // happen outside of a tokio runtime. Boy.
-    let result = warp::serve(routes)
-      .try_bind_ephemeral(addr)
-      .with_context(|| format!("failed to bind to {}", addr));
+    let result = match &acceptor {
+      Some(acceptor) => acceptor.bind(addr).map(|(addr, incoming)| {
+        let serve = warp::serve(routes)
+          .serve_incoming_with_graceful_shutdown(incoming, options.shutdown.clone().triggered());
+        (addr, Either::Right(serve))
+      }),
+      None => warp::serve(routes)
+        .try_bind_with_graceful_shutdown(addr, options.shutdown.clone().triggered())
+        .map(|(addr, serve)| (addr, Either::Left(serve)))
+        .with_context(|| format!("failed to bind to {}", addr)),
+    };
 
     match result {
       Ok(result) => break result,
@@ -160,7 +720,19 @@ pub fn serve(root: &Path, addr: SocketAddr) -> Result<(impl Future<Output = ()>,
     }
   };
 
-  let index = Index::new(root, &addr).with_context(|| {
+  // When serving HTTPS ourselves, clients need to be told so.
+  let public_url = options.public_url.clone().or_else(|| {
+    acceptor
+      .as_ref()
+      .map(|_acceptor| format!("https://{}", addr))
+  });
+  let index_options = IndexOptions {
+    auth_required: options.auth_required,
+    git_author: options.git_author.clone(),
+    read_only: options.read_only,
+  };
+  let index = Index::new(root, &addr, public_url.as_deref(), &index_options)
+  .with_context(|| {
     format!(
       "failed to create/instantiate crate index at {}",
       root.display()
@@ -169,6 +741,10 @@ pub fn serve(root: &Path, addr: SocketAddr) -> Result<(impl Future<Output = ()>,
 
   *shared.lock().unwrap() = Some(index);
 
+  if let Some(acceptor) = acceptor {
+    let _handle = spawn(acceptor.reload_on_hangup());
+  }
+
   Ok((serve, addr))
 }
 
@@ -179,6 +755,8 @@ mod tests {
 
   use serde_json::to_string;
 
+  use tempfile::tempdir;
+
 
   #[test]
   fn registry_error_encoding() {
@@ -191,4 +769,39 @@ mod tests {
 
     assert_eq!(to_string(&errors).unwrap(), expected);
   }
+
+  /// Check that we can parse public URLs.
+  #[test]
+  fn public_url_parsing() {
+    let segments = parse_public_url("https://example.com").unwrap();
+    assert!(segments.is_empty());
+
+    let segments = parse_public_url("http://example.com:8080/cargo/registry/").unwrap();
+    assert_eq!(segments, vec!["cargo".to_string(), "registry".to_string()]);
+
+    assert!(parse_public_url("example.com/registry").is_err());
+    assert!(parse_public_url("ftp://example.com").is_err());
+    assert!(parse_public_url("https://example.com/?foo=bar").is_err());
+  }
+
+  /// Check that waiting for access to the index does not block the
+  /// runtime.
+  #[tokio::test]
+  #[allow(clippy::await_holding_lock)]
+  async fn index_access_off_runtime() {
+    let root = tempdir().unwrap();
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let index = Index::new(root.path(), &addr, None, &IndexOptions::default()).unwrap();
+    let shared = Arc::new(Mutex::new(Some(index)));
+
+    // Pretend that a long running operation holds the index.
+    let guard = shared.lock().unwrap();
+    let handle = spawn(with_index(shared.clone(), |_index| Ok(42)));
+    // The runtime is free to make progress on other tasks meanwhile.
+    let () = spawn(async {}).await.unwrap();
+    assert!(!handle.is_finished());
+
+    drop(guard);
+    assert_eq!(handle.await.unwrap().unwrap(), 42);
+  }
 }
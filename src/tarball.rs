@@ -0,0 +1,259 @@
+// Copyright (C) 2026 The cargo-http-registry Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::io::Read as _;
+use std::path::Component;
+use std::path::Path;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::ensure;
+use anyhow::Context as _;
+use anyhow::Result;
+
+use flate2::read::GzDecoder;
+
+use serde::Deserialize;
+
+use tar::Archive;
+use tar::EntryType;
+
+
+/// The `[package]` section of a `Cargo.toml`.
+#[derive(Debug, Deserialize)]
+struct Package {
+  name: String,
+  version: String,
+}
+
+/// The parts of a `Cargo.toml` manifest we are interested in.
+#[derive(Debug, Deserialize)]
+struct Manifest {
+  package: Package,
+}
+
+
+/// Check that the path of an archive entry is contained in `prefix`.
+fn check_entry_path(path: &Path, prefix: &str) -> Result<()> {
+  let mut components = path.components();
+  ensure!(
+    components.next() == Some(Component::Normal(prefix.as_ref())),
+    "archive entry {} is not located below {}/",
+    path.display(),
+    prefix
+  );
+  ensure!(
+    components.all(|component| matches!(component, Component::Normal(_))),
+    "archive entry {} has an invalid path",
+    path.display()
+  );
+  Ok(())
+}
+
+
+/// Check that `data` is a valid `.crate` archive for crate `name` in
+/// version `version`.
+///
+/// The archive has to be a gzip compressed tarball with all entries
+/// residing below `<name>-<version>/`. Links are not permitted.
+/// Furthermore, it has to contain a `Cargo.toml` manifest whose name and
+/// version match the provided ones. The combined size of all entries
+/// must not exceed `max_unpacked_size` bytes.
+pub fn check_crate_tarball(
+  data: &[u8],
+  name: &str,
+  version: &str,
+  max_unpacked_size: u64,
+) -> Result<()> {
+  let prefix = format!("{}-{}", name, version);
+  let mut archive = Archive::new(GzDecoder::new(data));
+  let mut manifest = None;
+  let mut unpacked_size = 0u64;
+
+  let entries = archive
+    .entries()
+    .context("failed to read crate archive")?;
+  for entry in entries {
+    let mut entry = entry.context("failed to read crate archive entry")?;
+    let path = entry
+      .path()
+      .context("failed to read path of crate archive entry")?
+      .into_owned();
+    let () = check_entry_path(&path, &prefix)?;
+
+    // Check the size before any data are read, to stop decompressing
+    // as early as possible.
+    unpacked_size = unpacked_size.saturating_add(entry.size());
+    ensure!(
+      unpacked_size <= max_unpacked_size,
+      "unpacked crate size exceeds maximum of {} bytes",
+      max_unpacked_size
+    );
+
+    match entry.header().entry_type() {
+      EntryType::Regular | EntryType::Directory => (),
+      // Extended headers are consumed by the `tar` crate itself, but
+      // let's be explicit about accepting them.
+      EntryType::XHeader | EntryType::XGlobalHeader | EntryType::GNULongName => (),
+      ty => bail!(
+        "archive entry {} has unsupported type {:?}",
+        path.display(),
+        ty
+      ),
+    }
+
+    if path == Path::new(&prefix).join("Cargo.toml") {
+      let mut content = String::new();
+      let _count = entry
+        .read_to_string(&mut content)
+        .context("failed to read Cargo.toml from crate archive")?;
+      manifest = Some(content);
+    }
+  }
+
+  let manifest = manifest.ok_or_else(|| anyhow!("crate archive lacks Cargo.toml"))?;
+  let manifest =
+    toml::from_str::<Manifest>(&manifest).context("failed to parse Cargo.toml of crate")?;
+
+  ensure!(
+    manifest.package.name == name,
+    "crate name {} in Cargo.toml does not match published name {}",
+    manifest.package.name,
+    name
+  );
+  ensure!(
+    manifest.package.version == version,
+    "crate version {} in Cargo.toml does not match published version {}",
+    manifest.package.version,
+    version
+  );
+  Ok(())
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+  use super::*;
+
+  use flate2::write::GzEncoder;
+  use flate2::Compression;
+
+  use tar::Builder;
+  use tar::Header;
+
+
+  /// Create a `.crate` archive containing the given files.
+  pub(crate) fn crate_archive(files: &[(&str, EntryType, &[u8])]) -> Vec<u8> {
+    let encoder = GzEncoder::new(Vec::new(), Compression::default());
+    let mut builder = Builder::new(encoder);
+
+    for (path, ty, data) in files {
+      let mut header = Header::new_gnu();
+      header.set_entry_type(*ty);
+      header.set_size(data.len() as u64);
+      header.set_mode(0o644);
+      // Write the raw path to be able to craft malicious paths that
+      // `Header::set_path` would reject.
+      let name = &mut header.as_old_mut().name;
+      name[..path.len()].copy_from_slice(path.as_bytes());
+      header.set_cksum();
+      let () = builder.append(&header, *data).unwrap();
+    }
+
+    builder.into_inner().unwrap().finish().unwrap()
+  }
+
+  /// Create a valid `.crate` archive for crate `name` in version `vers`.
+  pub(crate) fn valid_crate(name: &str, vers: &str) -> Vec<u8> {
+    let manifest = format!("[package]\nname = \"{name}\"\nversion = \"{vers}\"\n");
+    crate_archive(&[
+      (
+        &format!("{name}-{vers}/Cargo.toml"),
+        EntryType::Regular,
+        manifest.as_bytes(),
+      ),
+      (
+        &format!("{name}-{vers}/src/lib.rs"),
+        EntryType::Regular,
+        b"",
+      ),
+    ])
+  }
+
+
+  /// Check that a valid crate archive is accepted.
+  #[test]
+  fn valid_archive() {
+    let data = valid_crate("foobar", "0.1.0");
+    let () = check_crate_tarball(&data, "foobar", "0.1.0", u64::MAX).unwrap();
+  }
+
+  /// Check that mismatches between meta data and manifest are detected.
+  #[test]
+  fn manifest_mismatch() {
+    let data = valid_crate("foobar", "0.1.0");
+    let err = check_crate_tarball(&data, "foobar", "0.2.0", u64::MAX).unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "archive entry foobar-0.1.0/Cargo.toml is not located below foobar-0.2.0/"
+    );
+
+    let manifest = b"[package]\nname = \"foobaz\"\nversion = \"0.1.0\"\n";
+    let data = crate_archive(&[(
+      "foobar-0.1.0/Cargo.toml",
+      EntryType::Regular,
+      manifest,
+    )]);
+    let err = check_crate_tarball(&data, "foobar", "0.1.0", u64::MAX).unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "crate name foobaz in Cargo.toml does not match published name foobar"
+    );
+
+    let data = crate_archive(&[("foobar-0.1.0/src/lib.rs", EntryType::Regular, b"")]);
+    let err = check_crate_tarball(&data, "foobar", "0.1.0", u64::MAX).unwrap_err();
+    assert_eq!(err.to_string(), "crate archive lacks Cargo.toml");
+  }
+
+  /// Check that malicious or unsupported archive entries are rejected.
+  #[test]
+  fn invalid_entries() {
+    let data = crate_archive(&[("foobar-0.1.0/../../x", EntryType::Regular, b"")]);
+    let err = check_crate_tarball(&data, "foobar", "0.1.0", u64::MAX).unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "archive entry foobar-0.1.0/../../x has an invalid path"
+    );
+
+    let data = crate_archive(&[("/etc/passwd", EntryType::Regular, b"")]);
+    assert!(check_crate_tarball(&data, "foobar", "0.1.0", u64::MAX).is_err());
+
+    let data = crate_archive(&[("foobar-0.1.0/link", EntryType::Symlink, b"")]);
+    let err = check_crate_tarball(&data, "foobar", "0.1.0", u64::MAX).unwrap_err();
+    assert!(err.to_string().contains("unsupported type"), "{err}");
+  }
+
+  /// Check that the unpacked size of an archive is limited.
+  #[test]
+  fn unpacked_size_limit() {
+    let data = crate_archive(&[
+      ("foobar-0.1.0/Cargo.toml", EntryType::Regular, &[b'#'; 512]),
+      ("foobar-0.1.0/src/lib.rs", EntryType::Regular, &[b'/'; 512]),
+    ]);
+    let err = check_crate_tarball(&data, "foobar", "0.1.0", 1023).unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "unpacked crate size exceeds maximum of 1023 bytes"
+    );
+
+    let err = check_crate_tarball(&data, "foobar", "0.1.0", 1024).unwrap_err();
+    assert_eq!(err.to_string(), "failed to parse Cargo.toml of crate");
+  }
+
+  /// Check that data that is not a gzip compressed tarball is rejected.
+  #[test]
+  fn invalid_archive() {
+    let err = check_crate_tarball(b"foobar", "foobar", "0.1.0", u64::MAX).unwrap_err();
+    assert!(err.to_string().starts_with("failed to read crate archive"));
+  }
+}
@@ -0,0 +1,311 @@
+// Copyright (C) 2026 The cargo-http-registry Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::net::TcpListener as StdTcpListener;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use anyhow::ensure;
+use anyhow::Context as _;
+use anyhow::Result;
+
+use futures_util::stream::unfold;
+use futures_util::Stream;
+use futures_util::StreamExt as _;
+
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+use tokio_rustls::rustls::crypto::ring::sign::any_supported_type;
+use tokio_rustls::rustls::server::ClientHello;
+use tokio_rustls::rustls::server::ResolvesServerCert;
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+use tracing::debug;
+use tracing::error;
+use tracing::info;
+use tracing::warn;
+
+
+/// The maximum number of TLS handshakes we perform concurrently.
+const MAX_PENDING_HANDSHAKES: usize = 64;
+/// The time a client has for completing the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+
+/// The certificate and private key to serve HTTPS with.
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+  /// The path to a PEM file containing the certificate chain, starting
+  /// with the server's certificate.
+  pub cert: PathBuf,
+  /// The path to a PEM file containing the private key belonging to
+  /// the server's certificate.
+  pub key: PathBuf,
+}
+
+impl TlsConfig {
+  /// Load the certificate chain and private key.
+  fn load(&self) -> Result<CertifiedKey> {
+    let open = |path: &Path| {
+      File::open(path)
+        .map(BufReader::new)
+        .with_context(|| format!("failed to open {}", path.display()))
+    };
+
+    let certs = rustls_pemfile::certs(&mut open(&self.cert)?)
+      .collect::<Result<Vec<_>, _>>()
+      .with_context(|| format!("failed to read certificates from {}", self.cert.display()))?;
+    ensure!(
+      !certs.is_empty(),
+      "{} does not contain any certificates",
+      self.cert.display()
+    );
+
+    let key = rustls_pemfile::private_key(&mut open(&self.key)?)
+      .with_context(|| format!("failed to read private key from {}", self.key.display()))?
+      .ok_or_else(|| anyhow!("{} does not contain a private key", self.key.display()))?;
+    let key = any_supported_type(&key)
+      .with_context(|| format!("private key in {} is not supported", self.key.display()))?;
+
+    Ok(CertifiedKey::new(certs, key))
+  }
+}
+
+
+/// A certificate resolver always providing the most recently loaded
+/// certificate.
+#[derive(Debug)]
+struct CertResolver {
+  key: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for CertResolver {
+  fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+    Some(self.key.read().unwrap().clone())
+  }
+}
+
+
+/// An acceptor of TLS connections whose certificate can be reloaded
+/// while running.
+#[derive(Clone)]
+pub struct Acceptor {
+  config: TlsConfig,
+  resolver: Arc<CertResolver>,
+  acceptor: TlsAcceptor,
+}
+
+impl Acceptor {
+  /// Create a new `Acceptor`, loading the certificate and key referenced
+  /// by `config`.
+  pub fn new(config: TlsConfig) -> Result<Self> {
+    let key = config.load()?;
+    let resolver = Arc::new(CertResolver {
+      key: RwLock::new(Arc::new(key)),
+    });
+    let server_config = ServerConfig::builder()
+      .with_no_client_auth()
+      .with_cert_resolver(resolver.clone());
+
+    let slf = Self {
+      config,
+      resolver,
+      acceptor: TlsAcceptor::from(Arc::new(server_config)),
+    };
+    Ok(slf)
+  }
+
+  /// Reload the certificate and key from disk.
+  ///
+  /// New connections use the reloaded certificate, while established
+  /// ones are unaffected.
+  pub fn reload(&self) -> Result<()> {
+    let key = self.config.load()?;
+    *self.resolver.key.write().unwrap() = Arc::new(key);
+    Ok(())
+  }
+
+  /// Reload the certificate whenever we receive a `SIGHUP`.
+  #[cfg(unix)]
+  pub async fn reload_on_hangup(self) {
+    use tokio::signal::unix::signal;
+    use tokio::signal::unix::SignalKind;
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+      Ok(hangup) => hangup,
+      Err(err) => {
+        error!("failed to install SIGHUP handler: {}", err);
+        return
+      },
+    };
+
+    while hangup.recv().await.is_some() {
+      match self.reload() {
+        Ok(()) => info!("reloaded TLS certificate"),
+        Err(err) => error!("failed to reload TLS certificate: {:#}", err),
+      }
+    }
+  }
+
+  /// Reload the certificate whenever we receive a `SIGHUP`.
+  #[cfg(not(unix))]
+  pub async fn reload_on_hangup(self) {}
+
+  /// Bind to `addr` and return a stream of established TLS connections.
+  ///
+  /// Connections failing the TLS handshake or not completing it within
+  /// a reasonable time are dropped. Note that the latter requires the
+  /// time driver of the runtime to be enabled.
+  pub fn bind(
+    &self,
+    addr: SocketAddr,
+  ) -> Result<(
+    SocketAddr,
+    impl Stream<Item = io::Result<TlsStream<TcpStream>>> + Send,
+  )> {
+    let listener =
+      StdTcpListener::bind(addr).with_context(|| format!("failed to bind to {}", addr))?;
+    let addr = listener
+      .local_addr()
+      .context("failed to retrieve local address")?;
+    let () = listener
+      .set_nonblocking(true)
+      .context("failed to make listener non-blocking")?;
+    let listener = TcpListener::from_std(listener).context("failed to register listener")?;
+
+    let acceptor = self.acceptor.clone();
+    let incoming = unfold(listener, |listener| async move {
+      let result = listener.accept().await;
+      Some((result, listener))
+    })
+    .filter_map(|result| async move {
+      match result {
+        Ok((stream, _addr)) => Some(stream),
+        Err(err) => {
+          warn!("failed to accept connection: {}", err);
+          None
+        },
+      }
+    })
+    .map(move |stream| timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)))
+    // Perform handshakes concurrently so that a single slow client
+    // cannot hold up everybody else. The timeout makes sure that idle
+    // clients cannot occupy all slots indefinitely.
+    .buffer_unordered(MAX_PENDING_HANDSHAKES)
+    .filter_map(|result| async move {
+      match result {
+        Ok(Ok(stream)) => Some(Ok(stream)),
+        Ok(Err(err)) => {
+          debug!("TLS handshake failed: {}", err);
+          None
+        },
+        Err(_elapsed) => {
+          debug!("TLS handshake timed out");
+          None
+        },
+      }
+    });
+
+    Ok((addr, incoming))
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::fs::write;
+
+  use rcgen::generate_simple_self_signed;
+
+  use tokio::io::AsyncReadExt as _;
+  use tokio::spawn;
+
+  use tempfile::tempdir;
+
+
+  /// Write a self-signed certificate for `127.0.0.1` and its key to
+  /// `dir`, returning the corresponding `TlsConfig`.
+  fn self_signed(dir: &Path) -> TlsConfig {
+    let cert = generate_simple_self_signed(vec!["127.0.0.1".to_string()]).unwrap();
+    let config = TlsConfig {
+      cert: dir.join("cert.pem"),
+      key: dir.join("key.pem"),
+    };
+    let () = write(&config.cert, cert.serialize_pem().unwrap()).unwrap();
+    let () = write(&config.key, cert.serialize_private_key_pem()).unwrap();
+    config
+  }
+
+
+  /// Check that we can load and reload certificates.
+  #[test]
+  fn certificate_reload() {
+    let dir = tempdir().unwrap();
+    let config = self_signed(dir.path());
+    let acceptor = Acceptor::new(config.clone()).unwrap();
+    let before = acceptor.resolver.key.read().unwrap().cert.clone();
+
+    let _config = self_signed(dir.path());
+    let () = acceptor.reload().unwrap();
+    let after = acceptor.resolver.key.read().unwrap().cert.clone();
+    assert_ne!(before, after);
+
+    // A failed reload keeps the previous certificate.
+    let () = write(&config.key, "garbage").unwrap();
+    assert!(acceptor.reload().is_err());
+    let current = acceptor.resolver.key.read().unwrap().cert.clone();
+    assert_eq!(current, after);
+  }
+
+  /// Check that clients not completing the TLS handshake get
+  /// disconnected.
+  #[tokio::test(start_paused = true)]
+  async fn handshake_timeout() {
+    let dir = tempdir().unwrap();
+    let acceptor = Acceptor::new(self_signed(dir.path())).unwrap();
+    let (addr, incoming) = acceptor.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let _handle = spawn(async move {
+      let mut incoming = Box::pin(incoming);
+      while incoming.next().await.is_some() {}
+    });
+
+    let mut idle = TcpStream::connect(addr).await.unwrap();
+    let mut buffer = Vec::new();
+    // With time paused, the timeout fires as soon as the runtime is
+    // idle, at which point the server closes the connection.
+    let count = idle.read_to_end(&mut buffer).await.unwrap();
+    assert_eq!(count, 0);
+  }
+
+  /// Check that invalid certificate files are reported.
+  #[test]
+  fn invalid_certificate() {
+    let dir = tempdir().unwrap();
+    let config = self_signed(dir.path());
+
+    let () = write(&config.cert, "").unwrap();
+    let err = Acceptor::new(config.clone()).err().unwrap();
+    assert!(err.to_string().ends_with("does not contain any certificates"));
+
+    let config = TlsConfig {
+      cert: dir.path().join("does-not-exist.pem"),
+      ..config
+    };
+    assert!(Acceptor::new(config).is_err());
+  }
+}
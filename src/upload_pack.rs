@@ -0,0 +1,358 @@
+// Copyright (C) 2026 The cargo-http-registry Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Support for serving the index via git's smart HTTP protocol.
+//!
+//! We implement the server side of `git-upload-pack` in its version 0
+//! (and 1) flavor and in stateless mode, as used over HTTP. We do not
+//! advertise `multi_ack`, meaning that the negotiation is kept simple:
+//! we acknowledge the first object we have in common with the client
+//! and then send a pack of everything the client asked for minus what
+//! is reachable from the common objects.
+//!
+//! Requests open the repository on their own instead of going through
+//! the `Index`, so that fetches neither wait for each other nor for
+//! publishes. Git makes sure that readers always see a consistent
+//! state.
+
+use std::io::Read as _;
+use std::path::Path;
+use std::str::from_utf8 as str_from_utf8;
+
+use anyhow::bail;
+use anyhow::ensure;
+use anyhow::Context as _;
+use anyhow::Result;
+
+use flate2::read::GzDecoder;
+
+use git2::Buf;
+use git2::ObjectType;
+use git2::Oid;
+use git2::Repository;
+
+use crate::index::refs;
+
+
+/// The content type of a reference advertisement.
+pub const ADVERTISEMENT_CONTENT_TYPE: &str = "application/x-git-upload-pack-advertisement";
+/// The content type of the result of an upload-pack request.
+pub const RESULT_CONTENT_TYPE: &str = "application/x-git-upload-pack-result";
+/// The maximum size of a (decompressed) upload-pack request, in bytes.
+pub const MAX_REQUEST_SIZE: u64 = 10 * 1024 * 1024;
+
+/// A flush packet.
+const FLUSH: &[u8] = b"0000";
+/// The capabilities we support.
+const CAPABILITIES: &str = "ofs-delta";
+
+
+/// Append `data` as a packet line to `out`.
+fn write_pkt_line(out: &mut Vec<u8>, data: &[u8]) {
+  out.extend_from_slice(format!("{:04x}", data.len() + 4).as_bytes());
+  out.extend_from_slice(data);
+}
+
+/// Split `data` into packet lines.
+///
+/// Flush (and delimiter) packets are reported as `None`.
+fn parse_pkt_lines(mut data: &[u8]) -> Result<Vec<Option<&[u8]>>> {
+  let mut lines = Vec::new();
+  while !data.is_empty() {
+    ensure!(data.len() >= 4, "truncated packet line length");
+    let len = str_from_utf8(&data[..4])
+      .ok()
+      .and_then(|len| usize::from_str_radix(len, 16).ok())
+      .context("invalid packet line length")?;
+
+    match len {
+      0 | 1 => {
+        lines.push(None);
+        data = &data[4..];
+      },
+      2 | 3 => bail!("invalid packet line length {}", len),
+      _ => {
+        ensure!(data.len() >= len, "truncated packet line");
+        lines.push(Some(&data[4..len]));
+        data = &data[len..];
+      },
+    }
+  }
+  Ok(lines)
+}
+
+/// Open the git repository of the registry at `root`.
+fn open_repository(root: &Path) -> Result<Repository> {
+  Repository::open(root)
+    .with_context(|| format!("failed to open git repository {}", root.display()))
+}
+
+/// Check whether `oid` is one of `tips` or reachable from one of them.
+fn is_reachable(repository: &Repository, oid: Oid, tips: &[Oid]) -> bool {
+  tips.contains(&oid)
+    || tips.iter().any(|tip| {
+      // Only commits are checked for reachability, which is all that
+      // clients ask for in practice.
+      repository.graph_descendant_of(*tip, oid).unwrap_or(false)
+    })
+}
+
+/// Parse an object ID at the start of `data`.
+fn parse_oid(data: &str) -> Result<Oid> {
+  let hex = data.get(..40).unwrap_or(data);
+  Oid::from_str(hex).with_context(|| format!("invalid object ID {}", hex))
+}
+
+
+/// Handler for `GET` requests to `/git/info/refs?service=git-upload-pack`.
+///
+/// Advertise the references of the registry at `root`.
+pub fn advertise_refs(root: &Path) -> Result<Vec<u8>> {
+  let repository = open_repository(root)?;
+  let mut out = Vec::new();
+  write_pkt_line(&mut out, b"# service=git-upload-pack\n");
+  out.extend_from_slice(FLUSH);
+
+  let mut lines = Vec::new();
+  let head = repository.head().ok();
+  if let Some((name, oid)) = head
+    .as_ref()
+    .and_then(|head| Some((head.name()?, head.target()?)))
+  {
+    lines.push((oid, "HEAD".to_string(), format!(" symref=HEAD:{}", name)));
+  }
+  for reference in refs(&repository)? {
+    lines.push((reference.oid, reference.name.clone(), String::new()));
+    if let Some(peeled) = reference.peeled {
+      lines.push((peeled, format!("{}^{{}}", reference.name), String::new()));
+    }
+  }
+
+  let agent = format!("agent=cargo-http-registry/{}", env!("CARGO_PKG_VERSION"));
+  if lines.is_empty() {
+    let line = format!("{} capabilities^{{}}\0{} {}\n", Oid::zero(), CAPABILITIES, agent);
+    write_pkt_line(&mut out, line.as_bytes());
+  }
+
+  for (i, (oid, name, symref)) in lines.into_iter().enumerate() {
+    let line = if i == 0 {
+      format!("{} {}\0{}{} {}\n", oid, name, CAPABILITIES, symref, agent)
+    } else {
+      format!("{} {}\n", oid, name)
+    };
+    write_pkt_line(&mut out, line.as_bytes());
+  }
+  out.extend_from_slice(FLUSH);
+  Ok(out)
+}
+
+
+/// Handler for `POST` requests to `/git/git-upload-pack`.
+///
+/// `body` is the client's request for objects from the registry at
+/// `root`, which is gzip compressed if `compressed` is `true`. Only
+/// objects reachable from the advertised references are served.
+pub fn upload_pack(root: &Path, body: &[u8], compressed: bool) -> Result<Vec<u8>> {
+  let mut decompressed = Vec::new();
+  let body = if compressed {
+    let _count = GzDecoder::new(body)
+      .take(MAX_REQUEST_SIZE + 1)
+      .read_to_end(&mut decompressed)
+      .context("failed to decompress request")?;
+    ensure!(
+      decompressed.len() as u64 <= MAX_REQUEST_SIZE,
+      "request exceeds maximum size of {} bytes",
+      MAX_REQUEST_SIZE
+    );
+    decompressed.as_slice()
+  } else {
+    body
+  };
+
+  let mut wants = Vec::new();
+  let mut haves = Vec::new();
+  let mut done = false;
+
+  for line in parse_pkt_lines(body)?.into_iter().flatten() {
+    let line = str_from_utf8(line).context("request contains invalid UTF-8")?;
+    let line = line.trim_end_matches('\n');
+    if let Some(want) = line.strip_prefix("want ") {
+      wants.push(parse_oid(want)?);
+    } else if let Some(have) = line.strip_prefix("have ") {
+      haves.push(parse_oid(have)?);
+    } else if line == "done" {
+      done = true;
+    } else if line.starts_with("shallow ") || line.starts_with("deepen") {
+      bail!("shallow fetches are not supported")
+    } else {
+      bail!("unexpected request line: {}", line)
+    }
+  }
+
+  let repository = open_repository(root)?;
+  let tips = refs(&repository)?
+    .into_iter()
+    .flat_map(|reference| [Some(reference.oid), reference.peeled])
+    .flatten()
+    .chain(repository.head().ok().and_then(|head| head.target()))
+    .collect::<Vec<_>>();
+  for want in &wants {
+    ensure!(
+      is_reachable(&repository, *want, &tips),
+      "upload-pack: not our ref {}",
+      want
+    );
+  }
+  let odb = repository.odb().context("failed to open git object database")?;
+  let common = haves
+    .into_iter()
+    .filter(|have| odb.exists(*have))
+    .collect::<Vec<_>>();
+
+  let mut out = Vec::new();
+  match common.first() {
+    Some(oid) => write_pkt_line(&mut out, format!("ACK {}\n", oid).as_bytes()),
+    None => write_pkt_line(&mut out, b"NAK\n"),
+  }
+
+  if !done || wants.is_empty() {
+    return Ok(out)
+  }
+
+  let mut builder = repository
+    .packbuilder()
+    .context("failed to create git pack builder")?;
+  let mut walk = repository
+    .revwalk()
+    .context("failed to create git revision walker")?;
+  for want in wants {
+    let object = repository
+      .find_object(want, None)
+      .with_context(|| format!("failed to find object {}", want))?;
+    match object.kind() {
+      Some(ObjectType::Commit) => walk.push(want)?,
+      Some(ObjectType::Tag) => {
+        let () = builder.insert_object(want, None)?;
+        let commit = object
+          .peel(ObjectType::Commit)
+          .with_context(|| format!("failed to peel tag {}", want))?;
+        walk.push(commit.id())?
+      },
+      _ => builder.insert_recursive(want, None)?,
+    }
+  }
+  for oid in common {
+    // Common objects that are not commits do not influence the walk.
+    let _result = walk.hide(oid);
+  }
+  let () = builder
+    .insert_walk(&mut walk)
+    .context("failed to add objects to pack")?;
+
+  let mut pack = Buf::new();
+  let () = builder
+    .write_buf(&mut pack)
+    .context("failed to create pack")?;
+  out.extend_from_slice(&pack);
+  Ok(out)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use tempfile::tempdir;
+
+  use crate::index::Index;
+  use crate::index::IndexOptions;
+
+
+  /// Check that we can parse packet lines.
+  #[test]
+  fn pkt_line_parsing() {
+    let mut data = Vec::new();
+    write_pkt_line(&mut data, b"want abc\n");
+    data.extend_from_slice(FLUSH);
+    write_pkt_line(&mut data, b"done\n");
+
+    let lines = parse_pkt_lines(&data).unwrap();
+    assert_eq!(
+      lines,
+      vec![Some(b"want abc\n".as_slice()), None, Some(b"done\n".as_slice())]
+    );
+
+    assert!(parse_pkt_lines(b"00").is_err());
+    assert!(parse_pkt_lines(b"zzzz").is_err());
+    assert!(parse_pkt_lines(b"0010abc").is_err());
+  }
+
+  /// Check that we advertise references and serve packs.
+  #[test]
+  fn advertisement_and_pack() {
+    let root = tempdir().unwrap();
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let _index = Index::new(root.path(), &addr, None, &IndexOptions::default()).unwrap();
+    let root = root.path();
+    let repository = Repository::open(root).unwrap();
+    let head = repository.head().unwrap();
+    let head_oid = head.target().unwrap();
+
+    let advertisement = advertise_refs(root).unwrap();
+    let lines = parse_pkt_lines(&advertisement).unwrap();
+    assert_eq!(lines[0], Some(b"# service=git-upload-pack\n".as_slice()));
+    assert_eq!(lines[1], None);
+    let first = str_from_utf8(lines[2].unwrap()).unwrap();
+    assert!(first.starts_with(&format!("{} HEAD\0", head_oid)), "{first}");
+    assert!(first.contains(&format!("symref=HEAD:{}", head.name().unwrap())));
+    assert_eq!(lines.last().unwrap(), &None);
+
+    // A request without `done` only negotiates.
+    let mut request = Vec::new();
+    write_pkt_line(&mut request, format!("want {} ofs-delta\n", head_oid).as_bytes());
+    request.extend_from_slice(FLUSH);
+    let response = upload_pack(root, &request, false).unwrap();
+    assert_eq!(response, b"0008NAK\n");
+
+    write_pkt_line(&mut request, b"done\n");
+    let response = upload_pack(root, &request, false).unwrap();
+    assert!(response.starts_with(b"0008NAK\nPACK"));
+
+    // Everything is known to the client already.
+    let mut request = Vec::new();
+    write_pkt_line(&mut request, format!("want {}\n", head_oid).as_bytes());
+    request.extend_from_slice(FLUSH);
+    write_pkt_line(&mut request, format!("have {}\n", head_oid).as_bytes());
+    write_pkt_line(&mut request, b"done\n");
+    let response = upload_pack(root, &request, false).unwrap();
+    let ack = format!("0031ACK {}\nPACK", head_oid);
+    assert!(response.starts_with(ack.as_bytes()));
+  }
+
+  /// Check that only objects reachable from our references are served.
+  #[test]
+  fn unreachable_wants() {
+    let root = tempdir().unwrap();
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let _index = Index::new(root.path(), &addr, None, &IndexOptions::default()).unwrap();
+    let root = root.path();
+    let repository = Repository::open(root).unwrap();
+    let head = repository.head().unwrap().peel_to_commit().unwrap();
+
+    let want = |oid: Oid| {
+      let mut request = Vec::new();
+      write_pkt_line(&mut request, format!("want {}\n", oid).as_bytes());
+      request.extend_from_slice(FLUSH);
+      write_pkt_line(&mut request, b"done\n");
+      upload_pack(root, &request, false)
+    };
+
+    // Ancestors of advertised commits are fine...
+    let parent = head.parent_id(0).unwrap();
+    assert!(want(parent).is_ok());
+    // ... but objects that merely exist are not.
+    let blob = repository.blob(b"not referenced").unwrap();
+    assert!(want(blob).is_err());
+    assert!(want(Oid::zero()).is_err());
+  }
+}
@@ -0,0 +1,82 @@
+// Copyright (C) 2026 The cargo-http-registry Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fs::read_to_string;
+
+use anyhow::bail;
+use anyhow::Context as _;
+use anyhow::Result;
+
+use serde_json::from_str;
+use serde_json::to_string;
+
+use crate::index::Entry;
+use crate::index::Index;
+use crate::owners::check_owner;
+use crate::publish::check_crate_name;
+use crate::publish::crate_index_path;
+
+
+/// Handler for the `/api/v1/crates/{name}/{version}/yank` and
+/// `/api/v1/crates/{name}/{version}/unyank` endpoints.
+///
+/// Set the `yanked` state of crate `name` in version `version` to
+/// `yanked`. Only owners of a crate are permitted to do so.
+pub fn yank_crate(
+  index: &mut Index,
+  user: Option<&str>,
+  name: &str,
+  version: &str,
+  yanked: bool,
+) -> Result<()> {
+  let () = check_crate_name(name)?;
+  let () = check_owner(index.root(), name, user)?;
+
+  let crate_meta_relative_path = crate_index_path(name);
+  let crate_meta_path = index.root().join(&crate_meta_relative_path);
+  let content = read_to_string(&crate_meta_path)
+    .with_context(|| format!("failed to read crate index file for crate {}", name))?;
+
+  let mut found = false;
+  let mut changed = false;
+  let mut new_content = String::with_capacity(content.len());
+
+  // Just like `read_entries`, we tolerate empty lines.
+  for line in content.lines().filter(|line| !line.is_empty()) {
+    let mut entry = from_str::<Entry>(line)
+      .with_context(|| format!("failed to parse crate index entry for crate {}", name))?;
+
+    if entry.vers == version {
+      found = true;
+      if entry.yanked != yanked {
+        changed = true;
+        entry.yanked = yanked;
+        // We only rewrite the line we actually change, so that other
+        // entries remain untouched byte-by-byte.
+        let line = to_string(&entry).context("failed to serialize crate index entry")?;
+        new_content.push_str(&line);
+        new_content.push('\n');
+        continue;
+      }
+    }
+
+    new_content.push_str(line);
+    new_content.push('\n');
+  }
+
+  if !found {
+    bail!("crate {} does not have a version {}", name, version)
+  }
+
+  if changed {
+    let action = if yanked { "Yank" } else { "Unyank" };
+    index
+      .update(
+        &[(&crate_meta_relative_path, new_content.as_bytes())],
+        &[],
+        &format!("{} {} in version {}", action, name, version),
+      )
+      .context("failed to commit changes to index")?;
+  }
+  Ok(())
+}
@@ -5,9 +5,14 @@
 
 use std::env;
 use std::fs::create_dir;
+use std::fs::read_to_string;
+use std::fs::write;
 use std::fs::OpenOptions;
+use std::io::Read as _;
 use std::io::Write as _;
 use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::TcpStream;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
@@ -16,6 +21,9 @@ use anyhow::bail;
 use anyhow::Context as _;
 use anyhow::Result;
 
+use sha2::Digest as _;
+use sha2::Sha256;
+
 use tempfile::tempdir;
 
 use test_fork::fork;
@@ -25,8 +33,12 @@ use tokio::task::JoinHandle;
 use tokio::test;
 
 use cargo_http_registry::serve;
+use cargo_http_registry::Options;
+use cargo_http_registry::Shutdown;
+use cargo_http_registry::TlsConfig;
 
 const REGISTRY: &str = "e2e-test-registry";
+const TOKEN: &str = "xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx";
 
 
 /// Escape all occurrences of `character` in `string`.
@@ -50,8 +62,21 @@ fn escape(character: &str, string: &str) -> String {
 enum Locator {
   /// A path on the file system to the root of the registry.
   Path(PathBuf),
-  /// A socket address for HTTP based access of the registry.
+  /// A socket address for HTTP based access of the registry, using the
+  /// git CLI for fetching.
   Socket(SocketAddr),
+  /// A socket address for HTTP based access of the registry, using
+  /// cargo's built-in git support for fetching.
+  SocketLibgit(SocketAddr),
+  /// A socket address for access of the registry using cargo's sparse
+  /// protocol.
+  Sparse(SocketAddr),
+  /// A base URL for access of the registry using cargo's sparse
+  /// protocol.
+  SparseUrl(String),
+  /// A socket address for access of the registry using cargo's sparse
+  /// protocol over HTTPS, along with the certificate to trust.
+  SparseTls(SocketAddr, PathBuf),
 }
 
 
@@ -88,9 +113,10 @@ fn setup_cargo_home(root: &Path, registry_locator: Locator) -> Result<PathBuf> {
         r#"
 [registries.{registry}]
 index = "file://{path}"
-token = "xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx"
+token = "{token}"
 "#,
         registry = REGISTRY,
+        token = TOKEN,
         // TODO: This is quite a ghetto way of escaping backslashes on,
         //       say, Windows paths. We could make it nice some day...
         path = escape("\\", &path.display().to_string()),
@@ -101,15 +127,74 @@ token = "xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx"
         r#"
 [registries.{registry}]
 index = "http://{addr}/git"
-token = "xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx"
+token = "{token}"
 
 [net]
 git-fetch-with-cli = true
 "#,
         registry = REGISTRY,
+        token = TOKEN,
+        addr = addr,
+      )
+    },
+    Locator::SocketLibgit(addr) => {
+      format!(
+        r#"
+[registries.{registry}]
+index = "http://{addr}/git"
+token = "{token}"
+
+[net]
+git-fetch-with-cli = false
+"#,
+        registry = REGISTRY,
+        token = TOKEN,
+        addr = addr,
+      )
+    },
+    Locator::Sparse(addr) => {
+      format!(
+        r#"
+[registries.{registry}]
+index = "sparse+http://{addr}/index/"
+token = "{token}"
+credential-provider = "cargo:token"
+"#,
+        registry = REGISTRY,
+        token = TOKEN,
         addr = addr,
       )
     },
+    Locator::SparseUrl(url) => {
+      format!(
+        r#"
+[registries.{registry}]
+index = "sparse+{url}/index/"
+token = "{token}"
+credential-provider = "cargo:token"
+"#,
+        registry = REGISTRY,
+        token = TOKEN,
+        url = url,
+      )
+    },
+    Locator::SparseTls(addr, cert) => {
+      format!(
+        r#"
+[registries.{registry}]
+index = "sparse+https://{addr}/index/"
+token = "{token}"
+credential-provider = "cargo:token"
+
+[http]
+cainfo = "{cert}"
+"#,
+        registry = REGISTRY,
+        token = TOKEN,
+        addr = addr,
+        cert = escape("\\", &cert.display().to_string()),
+      )
+    },
   };
 
   append(&config, data)?;
@@ -117,6 +202,20 @@ git-fetch-with-cli = true
 }
 
 
+/// Send a raw HTTP request to `addr` and return the response.
+async fn http(addr: SocketAddr, request: &str) -> String {
+  let request = request.replace("\r\n\r\n", "\r\nConnection: close\r\n\r\n");
+  let handle = tokio::task::spawn_blocking(move || {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    let () = stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    let _count = stream.read_to_string(&mut response).unwrap();
+    response
+  });
+  handle.await.unwrap()
+}
+
+
 /// Run a cargo command.
 async fn cargo<'s, I>(home: &Path, args: I) -> Result<()>
 where
@@ -135,6 +234,24 @@ where
   handle.await.unwrap()
 }
 
+/// Run a cargo command and capture its standard output.
+async fn cargo_stdout<'s, I>(home: &Path, args: I) -> Result<String>
+where
+  I: IntoIterator<Item = &'s str>,
+{
+  let mut command = Command::new("cargo");
+  command.env("CARGO_HOME", home).args(args);
+
+  let handle = tokio::task::spawn_blocking(move || {
+    let output = command.output().context("failed to execute cargo")?;
+    if !output.status.success() {
+      bail!("cargo failed execution")
+    }
+    String::from_utf8(output.stdout).context("cargo output is not valid UTF-8")
+  });
+  handle.await.unwrap()
+}
+
 /// Run 'cargo init' with the provided arguments and some sensible
 /// default ones.
 async fn cargo_init<'s, I>(home: &Path, args: I) -> Result<()>
@@ -143,7 +260,7 @@ where
 {
   let args = vec!["init", "--vcs", "none", "--registry", REGISTRY]
     .into_iter()
-    .chain(args.into_iter());
+    .chain(args);
 
   cargo(home, args).await
 }
@@ -163,7 +280,7 @@ where
     REGISTRY,
   ]
   .into_iter()
-  .chain(args.into_iter());
+  .chain(args);
 
   cargo(home, args).await
 }
@@ -174,7 +291,20 @@ where
 /// # Notes
 /// When invoked with `RegistryRootPath::Relative` this function changes
 /// the process' current working directory.
-fn serve_registry(root_path: RegistryRootPath) -> (JoinHandle<()>, PathBuf, SocketAddr) {
+fn serve_registry(
+  root_path: RegistryRootPath,
+  options: Options,
+) -> (JoinHandle<()>, PathBuf, SocketAddr) {
+  let addr = "127.0.0.1:0".parse().unwrap();
+  serve_registry_at(root_path, addr, options)
+}
+
+/// Serve our registry on the given address.
+fn serve_registry_at(
+  root_path: RegistryRootPath,
+  addr: SocketAddr,
+  options: Options,
+) -> (JoinHandle<()>, PathBuf, SocketAddr) {
   let root = tempdir().unwrap();
   let path = match root_path {
     RegistryRootPath::Absolute => root.path().to_owned(),
@@ -185,9 +315,8 @@ fn serve_registry(root_path: RegistryRootPath) -> (JoinHandle<()>, PathBuf, Sock
       PathBuf::from(index_dir)
     },
   };
-  let addr = "127.0.0.1:0".parse().unwrap();
 
-  let (serve, addr) = serve(&path, addr).unwrap();
+  let (serve, addr) = serve(&path, addr, options).unwrap();
   let serve = move || async {
     serve.await;
     // We need to reference `root` here to make sure that it is
@@ -208,7 +337,7 @@ fn serve_registry(root_path: RegistryRootPath) -> (JoinHandle<()>, PathBuf, Sock
 #[fork]
 async fn publish() {
   async fn test(root_path: RegistryRootPath) {
-    let (_handle, _reg_root, addr) = serve_registry(root_path);
+    let (_handle, _reg_root, addr) = serve_registry(root_path, Options::default());
 
     let src_root = tempdir().unwrap();
     let src_root = src_root.path();
@@ -235,10 +364,128 @@ async fn publish() {
 }
 
 
+/// Check that publishing requires a valid token if authentication is
+/// enabled.
+#[test]
+async fn publish_authenticated() {
+  async fn test(tokens: &str) -> Result<()> {
+    let dir = tempdir().unwrap();
+    let token_file = dir.path().join("tokens");
+    let () = write(&token_file, tokens).unwrap();
+
+    let options = Options {
+      token_file: Some(token_file),
+      ..Default::default()
+    };
+    let (_handle, _reg_root, addr) = serve_registry(RegistryRootPath::Absolute, options);
+
+    let src_root = tempdir().unwrap();
+    let src_root = src_root.path();
+    let home = setup_cargo_home(src_root, Locator::Sparse(addr)).unwrap();
+
+    let my_lib = src_root.join("my-lib");
+    cargo_init(&home, ["--lib", my_lib.to_str().unwrap()])
+      .await
+      .unwrap();
+
+    cargo_publish(
+      &home,
+      [
+        "--manifest-path",
+        my_lib.join("Cargo.toml").to_str().unwrap(),
+      ],
+    )
+    .await
+  }
+
+  let hash = Sha256::digest(TOKEN.as_bytes());
+  let () = test(&format!("user {hash:x}\n")).await.unwrap();
+
+  let hash = Sha256::digest(b"some-other-token");
+  let result = test(&format!("user {hash:x}\n")).await;
+  assert!(result.is_err());
+}
+
+
+/// Check that we can manage the owners of a crate and that only owners
+/// can publish new versions.
+#[test]
+async fn owners() {
+  let dir = tempdir().unwrap();
+  let token_file = dir.path().join("tokens");
+  let hash = Sha256::digest(TOKEN.as_bytes());
+  let other = Sha256::digest(b"some-other-token");
+  let () = write(&token_file, format!("user {hash:x}\nother {other:x}\n")).unwrap();
+
+  let options = Options {
+    token_file: Some(token_file),
+    ..Default::default()
+  };
+  let (_handle, _reg_root, addr) = serve_registry(RegistryRootPath::Absolute, options);
+
+  let src_root = tempdir().unwrap();
+  let src_root = src_root.path();
+  let home = setup_cargo_home(src_root, Locator::Sparse(addr)).unwrap();
+
+  let my_lib = src_root.join("my-lib");
+  cargo_init(&home, ["--lib", my_lib.to_str().unwrap()])
+    .await
+    .unwrap();
+  let my_lib_toml = my_lib.join("Cargo.toml");
+
+  cargo_publish(&home, ["--manifest-path", my_lib_toml.to_str().unwrap()])
+    .await
+    .unwrap();
+
+  let list = ["owner", "--registry", REGISTRY, "--list", "my-lib"];
+  let output = cargo_stdout(&home, list).await.unwrap();
+  assert_eq!(output.trim(), "user");
+
+  let add = ["owner", "--registry", REGISTRY, "--add", "unknown", "my-lib"];
+  let result = cargo(&home, add).await;
+  assert!(result.is_err());
+
+  let add = ["owner", "--registry", REGISTRY, "--add", "other", "my-lib"];
+  cargo(&home, add).await.unwrap();
+  let remove = ["owner", "--registry", REGISTRY, "--remove", "user", "my-lib"];
+  cargo(&home, remove).await.unwrap();
+
+  let output = cargo_stdout(&home, list).await.unwrap();
+  assert_eq!(output.trim(), "other");
+
+  // With ownership transferred, we should no longer be able to publish.
+  let manifest = read_to_string(&my_lib_toml).unwrap();
+  let manifest = manifest.replace(r#"version = "0.1.0""#, r#"version = "0.2.0""#);
+  let () = write(&my_lib_toml, manifest).unwrap();
+
+  let result = cargo_publish(&home, ["--manifest-path", my_lib_toml.to_str().unwrap()]).await;
+  assert!(result.is_err());
+}
+
+
+/// Check that we can publish and consume crates from a registry
+/// requiring authentication for all accesses.
+#[test]
+async fn get_auth_required() {
+  let dir = tempdir().unwrap();
+  let token_file = dir.path().join("tokens");
+  let hash = Sha256::digest(TOKEN.as_bytes());
+  let () = write(&token_file, format!("user {hash:x}\n")).unwrap();
+
+  let options = Options {
+    token_file: Some(token_file),
+    auth_required: true,
+    ..Default::default()
+  };
+  let (_handle, _, addr) = serve_registry(RegistryRootPath::Absolute, options);
+  test_publish_and_consume(Locator::Sparse(addr)).await
+}
+
+
 /// Check that we can publish crates with a renamed dependency.
 #[test]
 async fn publish_renamed() {
-  let (_handle, _reg_root, addr) = serve_registry(RegistryRootPath::Absolute);
+  let (_handle, _reg_root, addr) = serve_registry(RegistryRootPath::Absolute, Options::default());
 
   let src_root = tempdir().unwrap();
   let src_root = src_root.path();
@@ -334,17 +581,279 @@ async fn test_publish_and_consume(registry_locator: Locator) {
 }
 
 
+/// Check that we can yank and unyank a published crate.
+#[test]
+async fn yank() {
+  let (_handle, _, addr) = serve_registry(RegistryRootPath::Absolute, Options::default());
+
+  let src_root = tempdir().unwrap();
+  let src_root = src_root.path();
+  let home = setup_cargo_home(src_root, Locator::Sparse(addr)).unwrap();
+
+  let my_lib = src_root.join("my-lib");
+  cargo_init(&home, ["--lib", my_lib.to_str().unwrap()])
+    .await
+    .unwrap();
+  let my_lib_toml = my_lib.join("Cargo.toml");
+  let my_lib_toml = my_lib_toml.to_str().unwrap();
+
+  cargo_publish(&home, ["--manifest-path", my_lib_toml])
+    .await
+    .unwrap();
+
+  let my_bin = src_root.join("my-bin");
+  cargo_init(&home, ["--bin", my_bin.to_str().unwrap()])
+    .await
+    .unwrap();
+  let data = format!(r#"my-lib = {{version = "*", registry = "{REGISTRY}"}}"#);
+  append(&my_bin.join("Cargo.toml"), data).unwrap();
+  let my_bin_toml = my_bin.join("Cargo.toml");
+  let my_bin_toml = my_bin_toml.to_str().unwrap();
+
+  let yank = ["yank", "--registry", REGISTRY, "--version", "0.1.0", "my-lib"];
+  cargo(&home, yank).await.unwrap();
+  // Yanking twice is fine.
+  cargo(&home, yank).await.unwrap();
+
+  // With the only version yanked, dependency resolution has to fail.
+  let result = cargo(&home, ["check", "--manifest-path", my_bin_toml]).await;
+  assert!(result.is_err());
+
+  let unyank = [
+    "yank",
+    "--undo",
+    "--registry",
+    REGISTRY,
+    "--version",
+    "0.1.0",
+    "my-lib",
+  ];
+  cargo(&home, unyank).await.unwrap();
+  cargo(&home, ["check", "--manifest-path", my_bin_toml])
+    .await
+    .unwrap();
+
+  // Yanking a version that does not exist should fail.
+  let yank = ["yank", "--registry", REGISTRY, "--version", "0.2.0", "my-lib"];
+  let result = cargo(&home, yank).await;
+  assert!(result.is_err());
+}
+
+
+/// Check that we can search for published crates.
+#[test]
+async fn search() {
+  let (_handle, _, addr) = serve_registry(RegistryRootPath::Absolute, Options::default());
+
+  let src_root = tempdir().unwrap();
+  let src_root = src_root.path();
+  let home = setup_cargo_home(src_root, Locator::Sparse(addr)).unwrap();
+
+  for name in ["my-lib", "my-other-lib", "unrelated"] {
+    let path = src_root.join(name);
+    cargo_init(&home, ["--lib", path.to_str().unwrap()])
+      .await
+      .unwrap();
+    cargo_publish(
+      &home,
+      ["--manifest-path", path.join("Cargo.toml").to_str().unwrap()],
+    )
+    .await
+    .unwrap();
+  }
+
+  let output = cargo_stdout(&home, ["search", "--registry", REGISTRY, "lib"])
+    .await
+    .unwrap();
+  assert!(output.contains(r#"my-lib = "0.1.0""#), "{output}");
+  assert!(output.contains(r#"my-other-lib = "0.1.0""#), "{output}");
+  assert!(!output.contains("unrelated"), "{output}");
+}
+
+
 /// Check that we can consume a published crate over HTTP.
 #[test]
 async fn get_http() {
-  let (_handle, _, addr) = serve_registry(RegistryRootPath::Absolute);
+  let (_handle, _, addr) = serve_registry(RegistryRootPath::Absolute, Options::default());
   test_publish_and_consume(Locator::Socket(addr)).await
 }
 
 
+/// Check that we can consume a published crate over HTTP using cargo's
+/// built-in git support, which relies on the smart protocol.
+#[test]
+async fn get_http_libgit() {
+  let (_handle, _, addr) = serve_registry(RegistryRootPath::Absolute, Options::default());
+  test_publish_and_consume(Locator::SocketLibgit(addr)).await
+}
+
+
+/// Check that we can consume a published crate using the sparse
+/// protocol.
+#[test]
+async fn get_sparse() {
+  let (_handle, _, addr) = serve_registry(RegistryRootPath::Absolute, Options::default());
+  test_publish_and_consume(Locator::Sparse(addr)).await
+}
+
+
+/// Check that we can publish and consume a crate when the registry is
+/// served below a path prefix of a public URL.
+#[test]
+async fn get_public_url() {
+  // The public URL has to contain the port we end up serving on, so
+  // pick one up front.
+  let addr = TcpListener::bind("127.0.0.1:0")
+    .unwrap()
+    .local_addr()
+    .unwrap();
+  let url = format!("http://{addr}/cargo/registry");
+  let options = Options {
+    public_url: Some(url.clone()),
+    ..Default::default()
+  };
+  let (_handle, root, _) = serve_registry_at(RegistryRootPath::Absolute, addr, options);
+
+  let config = read_to_string(root.join("config.json")).unwrap();
+  assert!(config.contains(&format!(r#""api": "{url}""#)), "{config}");
+
+  test_publish_and_consume(Locator::SparseUrl(url)).await
+}
+
+
+/// Check that we can publish and consume a crate over HTTPS.
+#[test]
+async fn get_https() {
+  let dir = tempdir().unwrap();
+  let cert = rcgen::generate_simple_self_signed(vec!["127.0.0.1".to_string()]).unwrap();
+  let tls = TlsConfig {
+    cert: dir.path().join("cert.pem"),
+    key: dir.path().join("key.pem"),
+  };
+  let () = write(&tls.cert, cert.serialize_pem().unwrap()).unwrap();
+  let () = write(&tls.key, cert.serialize_private_key_pem()).unwrap();
+
+  let options = Options {
+    tls: Some(tls.clone()),
+    ..Default::default()
+  };
+  let (_handle, root, addr) = serve_registry(RegistryRootPath::Absolute, options);
+
+  let config = read_to_string(root.join("config.json")).unwrap();
+  assert!(config.contains(&format!(r#""api": "https://{addr}""#)), "{config}");
+
+  test_publish_and_consume(Locator::SparseTls(addr, tls.cert)).await
+}
+
+
 /// Check that we can consume a published crate through the file system.
 #[test]
 async fn get_filesystem() {
-  let (_handle, root, _) = serve_registry(RegistryRootPath::Absolute);
+  let (_handle, root, _) = serve_registry(RegistryRootPath::Absolute, Options::default());
   test_publish_and_consume(Locator::Path(root)).await
 }
+
+
+/// Check that we can shut down the registry server gracefully.
+#[test]
+async fn shutdown() {
+  let shutdown = Shutdown::default();
+  let options = Options {
+    shutdown: shutdown.clone(),
+    ..Default::default()
+  };
+  let (handle, _, addr) = serve_registry(RegistryRootPath::Absolute, options);
+  let _stream = TcpStream::connect(addr).unwrap();
+
+  let () = shutdown.trigger();
+  let () = handle.await.unwrap();
+  assert!(TcpStream::connect(addr).is_err());
+}
+
+
+/// Check that a read-only replica serves a registry but rejects
+/// modifications.
+#[test]
+async fn read_only() {
+  let (_handle, reg_root, addr) = serve_registry(RegistryRootPath::Absolute, Options::default());
+
+  let options = Options {
+    read_only: true,
+    ..Default::default()
+  };
+  let (serve, replica_addr) = serve(&reg_root, "127.0.0.1:0".parse().unwrap(), options).unwrap();
+  let _replica = spawn(serve);
+
+  // The replica advertises the registry as is.
+  let config = read_to_string(reg_root.join("config.json")).unwrap();
+  assert!(config.contains(&addr.to_string()), "{config}");
+  assert!(!config.contains(&replica_addr.to_string()), "{config}");
+
+  let request = "PUT /api/v1/crates/new HTTP/1.1\r\nContent-Length: 0\r\n\r\n";
+  let response = http(replica_addr, request).await;
+  assert!(response.contains("served read-only"), "{response}");
+
+  let src_root = tempdir().unwrap();
+  let src_root = src_root.path();
+  let home = setup_cargo_home(src_root, Locator::Sparse(addr)).unwrap();
+  let my_lib = src_root.join("my-lib");
+  cargo_init(&home, ["--lib", my_lib.to_str().unwrap()])
+    .await
+    .unwrap();
+  cargo_publish(
+    &home,
+    ["--manifest-path", my_lib.join("Cargo.toml").to_str().unwrap()],
+  )
+  .await
+  .unwrap();
+
+  // Crates published to the registry are visible through the replica.
+  let request = "GET /index/my/-l/my-lib HTTP/1.1\r\n\r\n";
+  let response = http(replica_addr, request).await;
+  assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+  assert!(response.contains(r#""name":"my-lib""#), "{response}");
+}
+
+
+/// Check that publish requests exceeding the size limit are rejected
+/// without reading the body.
+#[test]
+async fn publish_size_limit() {
+  let (_handle, _, addr) = serve_registry(RegistryRootPath::Absolute, Options::default());
+
+  let request = "PUT /api/v1/crates/new HTTP/1.1\r\nContent-Length: 1000000000\r\n\r\n";
+  let response = http(addr, request).await;
+  assert!(response.starts_with("HTTP/1.1 413"), "{response}");
+  // Cargo expects the reason in the registry's usual error format.
+  assert!(
+    response.contains(r#"{"errors":[{"detail":"request body exceeds the maximum size of "#),
+    "{response}"
+  );
+}
+
+
+/// Check that requests changing crate owners are authenticated and
+/// size limited before their body is read.
+#[test]
+async fn owners_change_limits() {
+  let dir = tempdir().unwrap();
+  let token_file = dir.path().join("tokens");
+  let hash = Sha256::digest(TOKEN.as_bytes());
+  let () = write(&token_file, format!("user {hash:x}\n")).unwrap();
+
+  let options = Options {
+    token_file: Some(token_file),
+    ..Default::default()
+  };
+  let (_handle, _reg_root, addr) = serve_registry(RegistryRootPath::Absolute, options);
+
+  let request = "PUT /api/v1/crates/foo/owners HTTP/1.1\r\nContent-Length: 1000000000\r\n\r\n";
+  let response = http(addr, request).await;
+  assert!(response.starts_with("HTTP/1.1 401"), "{response}");
+
+  let request = format!(
+    "PUT /api/v1/crates/foo/owners HTTP/1.1\r\nAuthorization: {TOKEN}\r\nContent-Length: 1000000000\r\n\r\n"
+  );
+  let response = http(addr, &request).await;
+  assert!(response.starts_with("HTTP/1.1 413"), "{response}");
+}